@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use super::color::*;
+use super::exr::*;
+use super::film::*;
+use super::tile::*;
+use super::vec3::*;
+
+// Radiance carried by one camera path, split by the number of bounces before it reached
+// the camera: emission is seen directly, direct light after one bounce and indirect
+// light after more. The three parts add up to the beauty sample.
+#[derive(Copy, Clone, Default)]
+pub struct PathSample {
+    pub emission: Color,
+    pub direct: Color,
+    pub indirect: Color,
+    pub first_hit: Option<FirstHit>,
+}
+
+#[derive(Copy, Clone)]
+pub struct FirstHit {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub depth: f64,
+    pub object_id: usize,
+}
+
+// Lighting layers are splatted with the same filter weights as the beauty pass and share
+// its weight sum. Surface layers are plain averages over the samples of the pixel; depth
+// only over the samples that hit something. The object id is that of the pixel's first
+// sample.
+#[derive(Copy, Clone, Default)]
+pub struct AovPixel {
+    pub emission_sum: Color,
+    pub direct_sum: Color,
+    pub indirect_sum: Color,
+    pub albedo_sum: Color,
+    pub normal_sum: Vec3,
+    pub depth_sum: f64,
+    pub hit_count: u32,
+    pub sample_count: u32,
+    pub object_id: usize,
+}
+
+impl PathSample {
+    pub fn from_color(color: Color) -> PathSample {
+        PathSample {
+            emission: color,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_light(&mut self, bounces: i32, light: Color) {
+        match bounces {
+            0 => self.emission += light,
+            1 => self.direct += light,
+            _ => self.indirect += light,
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        self.emission + self.direct + self.indirect
+    }
+}
+
+impl AovPixel {
+    fn surface_count(&self) -> f64 {
+        self.sample_count.max(1) as f64
+    }
+
+    pub fn albedo(&self) -> Color {
+        self.albedo_sum / self.surface_count()
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.normal_sum / self.surface_count()
+    }
+
+    // Infinite when no sample hit anything.
+    pub fn depth(&self) -> f64 {
+        if self.hit_count == 0 {
+            f64::INFINITY
+        } else {
+            self.depth_sum / self.hit_count as f64
+        }
+    }
+
+    pub fn splat(&mut self, weight: f64, sample: &PathSample) {
+        self.emission_sum += weight * sample.emission;
+        self.direct_sum += weight * sample.direct;
+        self.indirect_sum += weight * sample.indirect;
+    }
+
+    pub fn record_sample(&mut self, sample: &PathSample) {
+        if let Some(hit) = &sample.first_hit {
+            self.albedo_sum += hit.albedo;
+            self.normal_sum += hit.normal;
+            self.depth_sum += hit.depth;
+            self.hit_count += 1;
+            if self.sample_count == 0 {
+                self.object_id = hit.object_id;
+            }
+        }
+        self.sample_count += 1;
+    }
+}
+
+// Beauty and every AOV of `bounds` as one multi-layer image. Object ids are renumbered
+// from 1 in the order they first appear from the top-left of the image, 0 being empty
+// space, so they stay stable between runs.
+pub fn aov_image(film: &Film, bounds: &PixelBounds) -> ExrImage {
+    let mut channels: Vec<(String, Vec<f32>)> = [
+        "R",
+        "G",
+        "B",
+        "emission.R",
+        "emission.G",
+        "emission.B",
+        "direct.R",
+        "direct.G",
+        "direct.B",
+        "indirect.R",
+        "indirect.G",
+        "indirect.B",
+        "albedo.R",
+        "albedo.G",
+        "albedo.B",
+        "normal.X",
+        "normal.Y",
+        "normal.Z",
+        "depth.Z",
+        "object_id.id",
+    ]
+    .iter()
+    .map(|name| (name.to_string(), Vec::new()))
+    .collect();
+    let mut object_ids = HashMap::new();
+    for (x, y) in bounds.pixels() {
+        let pixel = film.aov_pixel(x, y).copied().unwrap_or_default();
+        let weight_sum = film.pixel(x, y).weight_sum;
+        let lighting = |sum: Color| {
+            if weight_sum == 0.0 {
+                Color::default()
+            } else {
+                sum / weight_sum
+            }
+        };
+        let object_id = if pixel.object_id == 0 {
+            0
+        } else {
+            let next_id = object_ids.len() + 1;
+            *object_ids.entry(pixel.object_id).or_insert(next_id)
+        };
+        let values = [
+            film.pixel_color(x, y),
+            lighting(pixel.emission_sum),
+            lighting(pixel.direct_sum),
+            lighting(pixel.indirect_sum),
+            pixel.albedo(),
+            pixel.normal(),
+        ]
+        .iter()
+        .flat_map(|color| vec![color.x, color.y, color.z])
+        .chain(vec![pixel.depth(), object_id as f64])
+        .collect::<Vec<f64>>();
+        for ((_, channel), value) in channels.iter_mut().zip(values) {
+            channel.push(value as f32);
+        }
+    }
+    ExrImage {
+        display_width: film.width,
+        display_height: film.height,
+        x0: bounds.x0,
+        y0: film.height - bounds.y1,
+        width: bounds.width(),
+        height: bounds.height(),
+        chromaticities: film.color_space.chromaticities(),
+        channels,
+    }
+}
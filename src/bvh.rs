@@ -4,6 +4,7 @@ use super::aabb::*;
 use super::hittable::*;
 use super::random::*;
 use super::ray::*;
+use super::stats::*;
 use super::vec3::*;
 
 enum BvhChild<'a> {
@@ -81,6 +82,7 @@ impl<'a> BvhNode<'a> {
 
 impl<'a> Hittable for BvhNode<'a> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::BvhNodeVisits);
         if self.aabb.hit(ray, t_min, t_max) {
             let hit_left = self.left.hit(ray, t_min, t_max);
             let hit_right = self.right.hit(
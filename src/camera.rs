@@ -1,6 +1,7 @@
 use super::angles::*;
 use super::random::*;
 use super::ray::*;
+use super::sampler::*;
 use super::vec3::*;
 
 pub struct Camera {
@@ -51,13 +52,22 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    // Differentials of a ray from `get_ray` for pixels `ds` and `dt` apart in the
+    // coordinates it takes; the neighbouring rays share its lens position.
+    pub fn differentials(&self, ray: &Ray, ds: f64, dt: f64) -> RayDifferentials {
+        RayDifferentials {
+            dx_direction: ray.direction + ds * self.horizontal,
+            dy_direction: ray.direction + dt * self.vertical,
+        }
+    }
+
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * sample_in_unit_disk(sampler.get_2d());
         let offset = self.u * rd.x + self.v * rd.y;
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-            random_double_in_limit(self.time0, self.time1),
+            self.time0 + (self.time1 - self.time0) * sampler.get_1d(),
         )
     }
 }
@@ -0,0 +1,85 @@
+extern crate libc;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Result, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::binio::*;
+use super::film::*;
+
+const MAGIC: &[u8; 8] = b"RTCKPT01";
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+// The first SIGINT asks the render loop to stop at the end of the current pass; a second
+// one terminates immediately.
+pub fn install_interrupt_handler() {
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// Samplers and the scene random number generator are pure functions of the seed, the
+// pixel and the sample index, so the film with its per-pixel sample counts is all the
+// state needed to resume. The fingerprint records every setting the film depends on.
+pub fn write_checkpoint(filename: &str, fingerprint: &str, film: &Film) -> Result<()> {
+    let temporary = format!("{}.tmp", filename);
+    {
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(fingerprint.len() as u32).to_le_bytes())?;
+        writer.write_all(fingerprint.as_bytes())?;
+        writer.write_all(&(film.pixels().len() as u64).to_le_bytes())?;
+        for pixel in film.pixels() {
+            pixel.write(&mut writer)?;
+        }
+        writer.flush()?;
+    }
+    std::fs::rename(&temporary, filename)
+}
+
+pub fn read_checkpoint(filename: &str, fingerprint: &str, film: &mut Film) -> Result<()> {
+    let mut reader = BufReader::new(File::open(filename)?);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data(format!(
+            "{} is not a checkpoint file",
+            filename
+        )));
+    }
+    let mut saved_fingerprint = vec![0u8; read_u32(&mut reader)? as usize];
+    reader.read_exact(&mut saved_fingerprint)?;
+    if saved_fingerprint != fingerprint.as_bytes() {
+        return Err(invalid_data(format!(
+            "checkpoint was rendered with '{}' but the current settings are '{}'",
+            String::from_utf8_lossy(&saved_fingerprint),
+            fingerprint
+        )));
+    }
+    let pixel_count = read_u64(&mut reader)? as usize;
+    if pixel_count != film.pixels().len() {
+        return Err(invalid_data(format!(
+            "checkpoint holds {} pixels, expected {}",
+            pixel_count,
+            film.pixels().len()
+        )));
+    }
+    let mut pixels = Vec::with_capacity(pixel_count);
+    for _ in 0..pixel_count {
+        pixels.push(FilmPixel::read(&mut reader)?);
+    }
+    film.set_pixels(pixels);
+    Ok(())
+}
@@ -1,3 +1,7 @@
 use super::vec3::*;
 
 pub type Color = Vec3;
+
+pub fn luminance(color: &Color) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
@@ -0,0 +1,163 @@
+use super::color::*;
+
+pub type Matrix3 = [[f64; 3]; 3];
+
+// RGB color spaces by their primaries. Render values are always linear; the transfer
+// functions only apply when decoding 8-bit textures and encoding 8-bit output.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColorSpace {
+    // Rec.709 primaries, D65 white.
+    Srgb,
+    // Rec.2020 primaries, D65 white.
+    Rec2020,
+    // ACES AP1 primaries, ACES (about D60) white.
+    AcesCg,
+}
+
+const SRGB_TO_XYZ: Matrix3 = [
+    [0.4123908, 0.3575843, 0.1804808],
+    [0.2126390, 0.7151687, 0.0721923],
+    [0.0193308, 0.1191948, 0.9505322],
+];
+const REC2020_TO_XYZ: Matrix3 = [
+    [0.6369580, 0.1446169, 0.1688810],
+    [0.2627002, 0.6779981, 0.0593017],
+    [0.0000000, 0.0280727, 1.0609851],
+];
+const ACESCG_TO_XYZ: Matrix3 = [
+    [0.6624542, 0.1340042, 0.1561877],
+    [0.2722287, 0.6740818, 0.0536895],
+    [-0.0055746, 0.0040607, 1.0103391],
+];
+const BRADFORD: Matrix3 = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Option<ColorSpace> {
+        match name {
+            "srgb" => Some(ColorSpace::Srgb),
+            "rec2020" => Some(ColorSpace::Rec2020),
+            "acescg" => Some(ColorSpace::AcesCg),
+            _ => None,
+        }
+    }
+
+    pub fn to_xyz(self) -> Matrix3 {
+        match self {
+            ColorSpace::Srgb => SRGB_TO_XYZ,
+            ColorSpace::Rec2020 => REC2020_TO_XYZ,
+            ColorSpace::AcesCg => ACESCG_TO_XYZ,
+        }
+    }
+
+    // CIE xy chromaticities of the red, green and blue primaries and of the white point.
+    pub fn chromaticities(self) -> [(f64, f64); 4] {
+        match self {
+            ColorSpace::Srgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), (0.3127, 0.3290)],
+            ColorSpace::Rec2020 => [
+                (0.708, 0.292),
+                (0.170, 0.797),
+                (0.131, 0.046),
+                (0.3127, 0.3290),
+            ],
+            ColorSpace::AcesCg => [
+                (0.713, 0.293),
+                (0.165, 0.830),
+                (0.128, 0.044),
+                (0.32168, 0.33767),
+            ],
+        }
+    }
+
+    // Display encoding of a linear value in [0, 1]: the sRGB curve, or the BT.1886
+    // gamma of 2.4 for Rec.2020 displays.
+    pub fn encode(self, value: f64) -> f64 {
+        match self {
+            ColorSpace::Srgb => {
+                if value <= 0.0031308 {
+                    12.92 * value
+                } else {
+                    1.055 * value.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            ColorSpace::Rec2020 | ColorSpace::AcesCg => value.powf(1.0 / 2.4),
+        }
+    }
+
+    // XYZ of the white point, with Y = 1.
+    pub fn white(self) -> Color {
+        transform_color(&self.to_xyz(), Color::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn is_display(self) -> bool {
+        self != ColorSpace::AcesCg
+    }
+}
+
+// Inverse of the sRGB transfer function, for 8-bit textures.
+pub fn srgb_decode(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn transform_color(matrix: &Matrix3, color: Color) -> Color {
+    Color::new(
+        matrix[0][0] * color.x + matrix[0][1] * color.y + matrix[0][2] * color.z,
+        matrix[1][0] * color.x + matrix[1][1] * color.y + matrix[1][2] * color.z,
+        matrix[2][0] * color.x + matrix[2][1] * color.y + matrix[2][2] * color.z,
+    )
+}
+
+pub fn multiply(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut product = [[0.0; 3]; 3];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+pub fn invert(m: &Matrix3) -> Matrix3 {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum::<f64>();
+    let mut inverse = [[0.0; 3]; 3];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = cofactor(j, i) / determinant;
+        }
+    }
+    inverse
+}
+
+// Bradford chromatic adaptation of XYZ values from one white point to another.
+pub fn adaptation_matrix(from_white: Color, to_white: Color) -> Matrix3 {
+    let from_cone = transform_color(&BRADFORD, from_white);
+    let to_cone = transform_color(&BRADFORD, to_white);
+    let scale = [
+        [to_cone.x / from_cone.x, 0.0, 0.0],
+        [0.0, to_cone.y / from_cone.y, 0.0],
+        [0.0, 0.0, to_cone.z / from_cone.z],
+    ];
+    multiply(&invert(&BRADFORD), &multiply(&scale, &BRADFORD))
+}
+
+// Linear RGB conversion between two spaces through XYZ, adapting between their white
+// points.
+pub fn conversion_matrix(from: ColorSpace, to: ColorSpace) -> Matrix3 {
+    let adaptation = adaptation_matrix(from.white(), to.white());
+    multiply(
+        &invert(&to.to_xyz()),
+        &multiply(&adaptation, &from.to_xyz()),
+    )
+}
@@ -3,6 +3,7 @@ use super::hittable::*;
 use super::material::*;
 use super::ray::*;
 use super::rectangle::*;
+use super::stats::*;
 use super::transform::*;
 use super::vec3::*;
 use super::world::*;
@@ -15,7 +16,7 @@ pub struct Cube<'a> {
 }
 
 impl<'a> Cube<'a> {
-    pub fn new(minimum: Vec3, maximum: Vec3, material: &'a dyn Material) -> Cube {
+    pub fn new(minimum: Vec3, maximum: Vec3, material: &'a dyn Material) -> Cube<'a> {
         Cube {
             minimum: minimum,
             maximum: maximum,
@@ -60,7 +61,7 @@ impl<'a> Cube<'a> {
                 x1: maximum.x,
                 z0: minimum.z,
                 z1: maximum.z,
-                k: maximum.z,
+                k: maximum.y,
             }),
             Box::new(YzRect {
                 mp: material,
@@ -84,12 +85,16 @@ impl<'a> Cube<'a> {
 
 impl<'a> Hittable for Cube<'a> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::CubeTests);
         let mut closest_record = None;
         let mut closest_so_far = t_max;
         for plane in &self.v_sides {
             if let Some(record) = plane.hit(ray, t_min, closest_so_far) {
                 closest_so_far = record.t;
-                closest_record = Some(record);
+                closest_record = Some(HitRecord {
+                    object_id: self as *const Self as usize,
+                    ..record
+                });
             }
         }
         return closest_record;
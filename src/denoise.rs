@@ -0,0 +1,178 @@
+use super::color::*;
+use super::film::*;
+use super::vec3::*;
+
+// Edge-stopping weights, as in Schied et al.'s spatiotemporal variance-guided filter.
+const SIGMA_LUMINANCE: f64 = 4.0;
+const NORMAL_POWER: f64 = 128.0;
+const SIGMA_DEPTH: f64 = 0.1;
+const SIGMA_ALBEDO: f64 = 0.2;
+const KERNEL: [f64; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+pub struct DenoiseSettings {
+    pub iterations: u32,
+}
+
+struct Feature {
+    albedo: Color,
+    normal: Vec3,
+    depth: f64,
+}
+
+// Edge-avoiding a-trous wavelet filter (Dammertz et al.) over the film. The lighting is
+// divided by the albedo before filtering and multiplied back afterwards so that texture
+// detail is kept; the albedo, normal and depth layers stop the filter at edges and the
+// per-pixel luminance variance makes it filter harder where the image is noisier. Needs
+// the film's AOV layers. Returns the denoised color of every pixel, in film order.
+pub fn denoise(film: &Film, settings: &DenoiseSettings) -> Vec<Color> {
+    let width = film.width as i64;
+    let height = film.height as i64;
+    let mut features = Vec::with_capacity((width * height) as usize);
+    let mut colors = Vec::with_capacity(features.capacity());
+    let mut variances = Vec::with_capacity(features.capacity());
+    for y in 0..film.height {
+        for x in 0..film.width {
+            let aov = film.aov_pixel(x, y).copied().unwrap_or_default();
+            let albedo = demodulation_albedo(&aov.albedo());
+            let pixel = film.pixel(x, y);
+            let normal = aov.normal();
+            features.push(Feature {
+                albedo,
+                normal: if normal.length() > 0.0 {
+                    normal.unit_vector()
+                } else {
+                    normal
+                },
+                depth: aov.depth(),
+            });
+            colors.push(film.pixel_color(x, y) / albedo);
+            let variance_of_mean = pixel.variance() / pixel.sample_count.max(1) as f64;
+            variances.push(variance_of_mean / luminance(&albedo).powi(2));
+        }
+    }
+    // At low sample counts many pixels have seen no light at all and report no variance,
+    // so the spread of the neighbouring pixels is used when it is larger.
+    let spatial_variances = spatial_variances(&colors, width, height);
+    for (variance, spatial_variance) in variances.iter_mut().zip(spatial_variances) {
+        *variance = variance.max(spatial_variance);
+    }
+    for iteration in 0..settings.iterations {
+        let step = 1i64 << iteration;
+        let mut filtered_colors = Vec::with_capacity(colors.len());
+        let mut filtered_variances = Vec::with_capacity(variances.len());
+        let smoothed_variances = blur_3x3(&variances, width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let p = (y * width + x) as usize;
+                let luminance_p = luminance(&colors[p]);
+                let luminance_scale = SIGMA_LUMINANCE * smoothed_variances[p].sqrt() + 1e-6;
+                let mut color_sum = Color::default();
+                let mut variance_sum = 0.0;
+                let mut weight_sum = 0.0;
+                for dy in -2..=2i64 {
+                    for dx in -2..=2i64 {
+                        let qx = x + dx * step;
+                        let qy = y + dy * step;
+                        if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                            continue;
+                        }
+                        let q = (qy * width + qx) as usize;
+                        let kernel =
+                            KERNEL[dx.unsigned_abs() as usize] * KERNEL[dy.unsigned_abs() as usize];
+                        let luminance_weight =
+                            (-(luminance_p - luminance(&colors[q])).abs() / luminance_scale).exp();
+                        let weight =
+                            kernel * luminance_weight * feature_weight(&features[p], &features[q]);
+                        color_sum += weight * colors[q];
+                        variance_sum += weight * weight * variances[q];
+                        weight_sum += weight;
+                    }
+                }
+                // The center tap always has a positive weight.
+                filtered_colors.push(color_sum / weight_sum);
+                filtered_variances.push(variance_sum / (weight_sum * weight_sum));
+            }
+        }
+        colors = filtered_colors;
+        variances = filtered_variances;
+    }
+    colors
+        .iter()
+        .zip(&features)
+        .map(|(color, feature)| *color * feature.albedo)
+        .collect()
+}
+
+// Variance of the luminance over the 5x5 neighbourhood of each pixel.
+fn spatial_variances(colors: &[Color], width: i64, height: i64) -> Vec<f64> {
+    let mut variances = Vec::with_capacity(colors.len());
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            let mut square_sum = 0.0;
+            let mut count = 0.0;
+            for qy in (y - 2).max(0)..(y + 3).min(height) {
+                for qx in (x - 2).max(0)..(x + 3).min(width) {
+                    let value = luminance(&colors[(qy * width + qx) as usize]);
+                    sum += value;
+                    square_sum += value * value;
+                    count += 1.0;
+                }
+            }
+            let mean = sum / count;
+            variances.push((square_sum / count - mean * mean).max(0.0));
+        }
+    }
+    variances
+}
+
+fn blur_3x3(values: &[f64], width: i64, height: i64) -> Vec<f64> {
+    let mut blurred = Vec::with_capacity(values.len());
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            let mut weight_sum = 0.0;
+            for qy in (y - 1).max(0)..(y + 2).min(height) {
+                for qx in (x - 1).max(0)..(x + 2).min(width) {
+                    let weight =
+                        if qx == x { 0.5 } else { 0.25 } * if qy == y { 0.5 } else { 0.25 };
+                    sum += weight * values[(qy * width + qx) as usize];
+                    weight_sum += weight;
+                }
+            }
+            blurred.push(sum / weight_sum);
+        }
+    }
+    blurred
+}
+
+// Texture to divide out of the lighting; dark or missing albedo is left in place.
+fn demodulation_albedo(albedo: &Color) -> Color {
+    let component = |value: f64| if value > 0.01 { value } else { 1.0 };
+    Color::new(
+        component(albedo.x),
+        component(albedo.y),
+        component(albedo.z),
+    )
+}
+
+fn feature_weight(p: &Feature, q: &Feature) -> f64 {
+    let normal_weight = p.normal.dot(&q.normal).max(0.0).powf(NORMAL_POWER);
+    let depth_weight = if p.depth.is_infinite() || q.depth.is_infinite() {
+        if p.depth == q.depth {
+            1.0
+        } else {
+            0.0
+        }
+    } else {
+        (-(p.depth - q.depth).abs() / (SIGMA_DEPTH * p.depth.max(1e-3))).exp()
+    };
+    let albedo_difference = p.albedo - q.albedo;
+    let albedo_weight =
+        (-albedo_difference.dot(&albedo_difference) / (SIGMA_ALBEDO * SIGMA_ALBEDO)).exp();
+    if p.normal.length() == 0.0 && q.normal.length() == 0.0 {
+        depth_weight * albedo_weight
+    } else {
+        normal_weight * depth_weight * albedo_weight
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::binio::*;
+use super::film::*;
+use super::progress::*;
+use super::render::*;
+use super::sampler::*;
+use super::tile::*;
+
+// Line-based protocol, one TCP connection per worker:
+//   worker      -> coordinator  HELLO <fingerprint>
+//   coordinator -> worker       OK | ERROR <message>
+//   coordinator -> worker       TILE <x0> <y0> <x1> <y1> <samples>
+//   worker      -> coordinator  RESULT <x0> <y0> <x1> <y1>, then one binary FilmPixel per
+//                               pixel of that footprint
+//   coordinator -> worker       DONE
+// The footprint is the tile grown by the filter radius, since samples splat across tile
+// borders. A tile whose worker disconnects goes back to the queue.
+
+struct TileResult {
+    tile: PixelBounds,
+    footprint: PixelBounds,
+    pixels: Vec<FilmPixel>,
+}
+
+struct WorkQueue {
+    tiles: Mutex<VecDeque<PixelBounds>>,
+    tile_count: usize,
+    completed: AtomicUsize,
+    connected_workers: AtomicUsize,
+    samples: u32,
+    fingerprint: String,
+    image: PixelBounds,
+}
+
+pub fn run_coordinator(
+    address: &str,
+    fingerprint: &str,
+    film: &mut Film,
+    tiles: Vec<PixelBounds>,
+    samples: u32,
+    progress: &Progress,
+) -> Result<()> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("waiting for workers on {}", listener.local_addr()?);
+    let tile_count = tiles.len();
+    progress.begin(
+        tiles
+            .iter()
+            .map(|tile| (tile.width() * tile.height()) as u64 * samples as u64)
+            .sum(),
+    );
+    let queue = Arc::new(WorkQueue {
+        tiles: Mutex::new(tiles.into_iter().collect()),
+        tile_count,
+        completed: AtomicUsize::new(0),
+        connected_workers: AtomicUsize::new(0),
+        samples,
+        fingerprint: fingerprint.to_string(),
+        image: PixelBounds::new(0, 0, film.width, film.height),
+    });
+    let (sender, receiver) = mpsc::channel();
+    let accepting_queue = queue.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let queue = accepting_queue.clone();
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map(|address| address.to_string())
+                    .unwrap_or_default();
+                eprintln!("worker {} connected", peer);
+                queue.connected_workers.fetch_add(1, Ordering::SeqCst);
+                if let Err(error) = serve_worker(stream, &queue, &sender) {
+                    eprintln!("worker {} dropped: {}", peer, error);
+                }
+                queue.connected_workers.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+    for result in receiver.iter().take(tile_count) {
+        film.merge_pixels(&result.footprint, &result.pixels);
+        progress.add_tile();
+        progress.add_samples((result.tile.width() * result.tile.height()) as u64 * samples as u64);
+    }
+    // Give idle workers the chance to receive DONE before the process exits.
+    let start = Instant::now();
+    while queue.connected_workers.load(Ordering::SeqCst) > 0 && start.elapsed().as_secs() < 5 {
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+fn serve_worker(
+    stream: TcpStream,
+    queue: &WorkQueue,
+    sender: &mpsc::Sender<TileResult>,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let hello = read_line(&mut reader)?;
+    if hello != format!("HELLO {}", queue.fingerprint) {
+        writeln!(writer, "ERROR coordinator runs '{}'", queue.fingerprint)?;
+        writer.flush()?;
+        return Err(invalid_data(format!("unexpected greeting '{}'", hello)));
+    }
+    writeln!(writer, "OK")?;
+    writer.flush()?;
+    loop {
+        let next_tile = queue.tiles.lock().unwrap().pop_front();
+        let tile = match next_tile {
+            Some(tile) => tile,
+            None if queue.completed.load(Ordering::SeqCst) == queue.tile_count => {
+                writeln!(writer, "DONE")?;
+                writer.flush()?;
+                return Ok(());
+            }
+            None => {
+                // Other workers still hold tiles which may come back to the queue.
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        match request_tile(&mut reader, &mut writer, &tile, queue) {
+            Ok(result) => {
+                queue.completed.fetch_add(1, Ordering::SeqCst);
+                sender
+                    .send(result)
+                    .map_err(|_| Error::new(ErrorKind::BrokenPipe, "coordinator stopped"))?;
+            }
+            Err(error) => {
+                queue.tiles.lock().unwrap().push_back(tile);
+                return Err(error);
+            }
+        }
+    }
+}
+
+fn request_tile(
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut BufWriter<TcpStream>,
+    tile: &PixelBounds,
+    queue: &WorkQueue,
+) -> Result<TileResult> {
+    writeln!(
+        writer,
+        "TILE {} {} {} {} {}",
+        tile.x0, tile.y0, tile.x1, tile.y1, queue.samples
+    )?;
+    writer.flush()?;
+    let line = read_line(reader)?;
+    let footprint = match parse_command(&line, "RESULT", 4)?.as_slice() {
+        [x0, y0, x1, y1] => PixelBounds::new(*x0, *y0, *x1, *y1),
+        _ => return Err(invalid_data(format!("unexpected reply '{}'", line))),
+    };
+    if footprint.x0 > footprint.x1
+        || footprint.y0 > footprint.y1
+        || footprint.intersect(&queue.image) != footprint
+    {
+        return Err(invalid_data(format!(
+            "footprint out of the image '{}'",
+            line
+        )));
+    }
+    let mut pixels = Vec::with_capacity((footprint.width() * footprint.height()) as usize);
+    for _ in 0..footprint.width() * footprint.height() {
+        pixels.push(FilmPixel::read(reader)?);
+    }
+    Ok(TileResult {
+        tile: *tile,
+        footprint,
+        pixels,
+    })
+}
+
+pub fn run_worker(
+    address: &str,
+    fingerprint: &str,
+    renderer: &Renderer,
+    make_film: &dyn Fn() -> Film,
+    sampler_kind: SamplerKind,
+    seed: u64,
+) -> Result<()> {
+    let stream = connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    writeln!(writer, "HELLO {}", fingerprint)?;
+    writer.flush()?;
+    let reply = read_line(&mut reader)?;
+    if reply != "OK" {
+        return Err(invalid_data(reply));
+    }
+    loop {
+        let line = read_line(&mut reader)?;
+        if line == "DONE" {
+            return Ok(());
+        }
+        let (tile, samples) = match parse_command(&line, "TILE", 5)?.as_slice() {
+            [x0, y0, x1, y1, samples] => (PixelBounds::new(*x0, *y0, *x1, *y1), *samples),
+            _ => return Err(invalid_data(format!("unexpected request '{}'", line))),
+        };
+        let mut film = make_film();
+        let tile = tile.intersect(&PixelBounds::new(0, 0, film.width, film.height));
+        let mut sampler = sampler_kind.make(samples, seed);
+        renderer.render_tile(&mut film, sampler.as_mut(), &tile, samples);
+        let footprint = film.footprint(&tile);
+        writeln!(
+            writer,
+            "RESULT {} {} {} {}",
+            footprint.x0, footprint.y0, footprint.x1, footprint.y1
+        )?;
+        for (x, y) in footprint.pixels() {
+            film.pixel(x, y).write(&mut writer)?;
+        }
+        writer.flush()?;
+        eprintln!(
+            "rendered tile {} {} {} {}",
+            tile.x0, tile.y0, tile.x1, tile.y1
+        );
+    }
+}
+
+// Workers are often started before the coordinator, so keep retrying for a while.
+fn connect(address: &str) -> Result<TcpStream> {
+    let mut attempts = 0;
+    loop {
+        match TcpStream::connect(address) {
+            Ok(stream) => return Ok(stream),
+            Err(error) if attempts >= 30 => return Err(error),
+            Err(_) => {
+                attempts += 1;
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
+
+fn read_line(reader: &mut dyn BufRead) -> Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "connection closed by peer",
+        ));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+fn parse_command(line: &str, command: &str, count: usize) -> Result<Vec<u32>> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some(command) {
+        return Err(invalid_data(format!(
+            "expected {}, got '{}'",
+            command, line
+        )));
+    }
+    let values = words
+        .map(|word| word.parse())
+        .collect::<std::result::Result<Vec<u32>, _>>()
+        .map_err(|_| invalid_data(format!("malformed line '{}'", line)))?;
+    if values.len() != count {
+        return Err(invalid_data(format!("malformed line '{}'", line)));
+    }
+    Ok(values)
+}
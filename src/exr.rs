@@ -0,0 +1,127 @@
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const PIXEL_TYPE_FLOAT: i32 = 2;
+
+// Single-part scanline OpenEXR image with uncompressed 32-bit float channels. The data
+// window (x0, y0, width, height) lies inside the display window and uses EXR
+// coordinates, with y growing downwards. Channel values are stored row by row from the
+// top of the data window; a name like "direct.R" puts the channel in layer "direct".
+pub struct ExrImage {
+    pub display_width: u32,
+    pub display_height: u32,
+    pub x0: u32,
+    pub y0: u32,
+    pub width: u32,
+    pub height: u32,
+    pub chromaticities: [(f64, f64); 4],
+    pub channels: Vec<(String, Vec<f32>)>,
+}
+
+impl ExrImage {
+    pub fn write(&mut self, filename: &str) -> Result<()> {
+        // Readers expect the channel list in alphabetical order.
+        self.channels.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&2u32.to_le_bytes());
+
+        let mut channel_list = Vec::new();
+        for (name, _) in &self.channels {
+            channel_list.extend_from_slice(name.as_bytes());
+            channel_list.push(0);
+            channel_list.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+            // pLinear and three reserved bytes, then the x and y sampling rates.
+            channel_list.extend_from_slice(&[0, 0, 0, 0]);
+            channel_list.extend_from_slice(&1i32.to_le_bytes());
+            channel_list.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channel_list.push(0);
+        write_attribute(&mut header, "channels", "chlist", &channel_list);
+        let chromaticities: Vec<u8> = self
+            .chromaticities
+            .iter()
+            .flat_map(|(x, y)| [*x as f32, *y as f32])
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        write_attribute(
+            &mut header,
+            "chromaticities",
+            "chromaticities",
+            &chromaticities,
+        );
+        write_attribute(&mut header, "compression", "compression", &[0]);
+        let data_window = [
+            self.x0 as i32,
+            self.y0 as i32,
+            (self.x0 + self.width) as i32 - 1,
+            (self.y0 + self.height) as i32 - 1,
+        ];
+        write_attribute(&mut header, "dataWindow", "box2i", &box2i(data_window));
+        let display_window = [
+            0,
+            0,
+            self.display_width as i32 - 1,
+            self.display_height as i32 - 1,
+        ];
+        write_attribute(
+            &mut header,
+            "displayWindow",
+            "box2i",
+            &box2i(display_window),
+        );
+        write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        write_attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        write_attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        header.push(0);
+
+        // One chunk per scanline: its y coordinate, its size and then every channel in turn.
+        let line_size = 4 * self.width as usize * self.channels.len();
+        let chunk_size = 8 + line_size;
+        let first_chunk = header.len() + 8 * self.height as usize;
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_all(&header)?;
+        for row in 0..self.height as usize {
+            writer.write_all(&((first_chunk + row * chunk_size) as u64).to_le_bytes())?;
+        }
+        for row in 0..self.height as usize {
+            writer.write_all(&(self.y0 as i32 + row as i32).to_le_bytes())?;
+            writer.write_all(&(line_size as i32).to_le_bytes())?;
+            for (_, values) in &self.channels {
+                let start = row * self.width as usize;
+                for value in &values[start..start + self.width as usize] {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        writer.flush()
+    }
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn box2i(values: [i32; 4]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
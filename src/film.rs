@@ -0,0 +1,282 @@
+use std::io::{Read, Result, Write};
+
+use super::aov::*;
+use super::binio::*;
+use super::color::*;
+use super::colorspace::*;
+use super::constants::*;
+use super::filter::*;
+use super::ppm::*;
+use super::tile::*;
+use super::tonemap::*;
+
+#[derive(Copy, Clone, Default)]
+pub struct FilmPixel {
+    pub rgb_sum: Color,
+    pub weight_sum: f64,
+    pub sample_count: u32,
+    pub luminance_mean: f64,
+    pub luminance_m2: f64,
+}
+
+impl FilmPixel {
+    // Welford's online update over the luminance of the samples taken inside this pixel.
+    fn record_sample(&mut self, color: &Color) {
+        let value = luminance(color);
+        self.sample_count += 1;
+        let delta = value - self.luminance_mean;
+        self.luminance_mean += delta / self.sample_count as f64;
+        self.luminance_m2 += delta * (value - self.luminance_mean);
+    }
+
+    // Chan et al.'s pairwise combination of two sets of luminance statistics.
+    pub fn merge(&mut self, other: &FilmPixel) {
+        self.rgb_sum += other.rgb_sum;
+        self.weight_sum += other.weight_sum;
+        if other.sample_count > 0 {
+            let count = self.sample_count + other.sample_count;
+            let delta = other.luminance_mean - self.luminance_mean;
+            let other_fraction = other.sample_count as f64 / count as f64;
+            self.luminance_m2 +=
+                other.luminance_m2 + delta * delta * self.sample_count as f64 * other_fraction;
+            self.luminance_mean += delta * other_fraction;
+            self.sample_count = count;
+        }
+    }
+
+    pub fn variance(&self) -> f64 {
+        if self.sample_count < 2 {
+            0.0
+        } else {
+            self.luminance_m2 / (self.sample_count - 1) as f64
+        }
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        for value in &[
+            self.rgb_sum.x,
+            self.rgb_sum.y,
+            self.rgb_sum.z,
+            self.weight_sum,
+            self.luminance_mean,
+            self.luminance_m2,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&self.sample_count.to_le_bytes())
+    }
+
+    pub fn read(reader: &mut dyn Read) -> Result<FilmPixel> {
+        let rgb_sum = Color::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?);
+        Ok(FilmPixel {
+            rgb_sum,
+            weight_sum: read_f64(reader)?,
+            luminance_mean: read_f64(reader)?,
+            luminance_m2: read_f64(reader)?,
+            sample_count: read_u32(reader)?,
+        })
+    }
+}
+
+// Film coordinates have their origin at the bottom-left corner of the image, with pixel
+// (x, y) covering [x, x + 1) x [y, y + 1). Colors are linear in the working color space.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub color_space: ColorSpace,
+    pixels: Vec<FilmPixel>,
+    aov_pixels: Option<Vec<AovPixel>>,
+    filter: Box<dyn Filter>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Box<dyn Filter>, color_space: ColorSpace) -> Film {
+        Film {
+            width,
+            height,
+            color_space,
+            pixels: vec![Default::default(); (width * height) as usize],
+            aov_pixels: None,
+            filter,
+        }
+    }
+
+    fn pixel_index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> &FilmPixel {
+        &self.pixels[self.pixel_index(x, y)]
+    }
+
+    pub fn pixels(&self) -> &[FilmPixel] {
+        &self.pixels
+    }
+
+    pub fn set_pixels(&mut self, pixels: Vec<FilmPixel>) {
+        self.pixels = pixels;
+    }
+
+    pub fn enable_aovs(&mut self) {
+        self.aov_pixels = Some(vec![Default::default(); self.pixels.len()]);
+    }
+
+    pub fn aov_pixel(&self, x: u32, y: u32) -> Option<&AovPixel> {
+        let index = self.pixel_index(x, y);
+        self.aov_pixels.as_ref().map(|pixels| &pixels[index])
+    }
+
+    // The pixels a tile's samples can reach through the reconstruction filter.
+    pub fn footprint(&self, tile: &PixelBounds) -> PixelBounds {
+        let radius = self.filter.radius().ceil() as u32;
+        PixelBounds::new(
+            tile.x0.saturating_sub(radius),
+            tile.y0.saturating_sub(radius),
+            tile.x1 + radius,
+            tile.y1 + radius,
+        )
+        .intersect(&PixelBounds::new(0, 0, self.width, self.height))
+    }
+
+    // `pixels` cover `bounds` in `PixelBounds::pixels` order.
+    pub fn merge_pixels(&mut self, bounds: &PixelBounds, pixels: &[FilmPixel]) {
+        for ((x, y), pixel) in bounds.pixels().zip(pixels) {
+            let index = self.pixel_index(x, y);
+            self.pixels[index].merge(pixel);
+        }
+    }
+
+    // Non-finite samples are counted as black so that they still advance the pixel's
+    // sample count.
+    pub fn add_sample(&mut self, film_x: f64, film_y: f64, sample: &PathSample) {
+        let sample = if is_finite(&sample.color()) {
+            *sample
+        } else {
+            PathSample {
+                first_hit: sample.first_hit,
+                ..Default::default()
+            }
+        };
+        let color = sample.color();
+        if film_x >= 0.0
+            && film_y >= 0.0
+            && film_x < self.width as f64
+            && film_y < self.height as f64
+        {
+            let index = self.pixel_index(film_x as u32, film_y as u32);
+            self.pixels[index].record_sample(&color);
+            if let Some(aov_pixels) = &mut self.aov_pixels {
+                aov_pixels[index].record_sample(&sample);
+            }
+        }
+        let radius = self.filter.radius();
+        let x0 = (film_x - 0.5 - radius).ceil().max(0.0) as u32;
+        let x1 = (film_x - 0.5 + radius).floor().min(self.width as f64 - 1.0);
+        let y0 = (film_y - 0.5 - radius).ceil().max(0.0) as u32;
+        let y1 = (film_y - 0.5 + radius)
+            .floor()
+            .min(self.height as f64 - 1.0);
+        if x1 < 0.0 || y1 < 0.0 {
+            return;
+        }
+        for y in y0..=(y1 as u32) {
+            for x in x0..=(x1 as u32) {
+                let weight = self
+                    .filter
+                    .evaluate(film_x - (x as f64 + 0.5), film_y - (y as f64 + 0.5));
+                if weight != 0.0 {
+                    let index = self.pixel_index(x, y);
+                    let pixel = &mut self.pixels[index];
+                    pixel.rgb_sum += weight * color;
+                    pixel.weight_sum += weight;
+                    if let Some(aov_pixels) = &mut self.aov_pixels {
+                        aov_pixels[index].splat(weight, &sample);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn pixel_color(&self, x: u32, y: u32) -> Color {
+        let pixel = self.pixel(x, y);
+        if pixel.weight_sum == 0.0 {
+            Color::default()
+        } else {
+            pixel.rgb_sum / pixel.weight_sum
+        }
+    }
+
+    // Standard error of the pixel mean relative to its luminance.
+    pub fn pixel_error(&self, x: u32, y: u32) -> f64 {
+        let pixel = self.pixel(x, y);
+        if pixel.sample_count < 2 {
+            INFINITY
+        } else {
+            (pixel.variance() / pixel.sample_count as f64).sqrt() / (pixel.luminance_mean + 1e-3)
+        }
+    }
+
+    pub fn max_sample_count(&self) -> u32 {
+        self.pixels
+            .iter()
+            .map(|pixel| pixel.sample_count)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn sample_count_ppm(&self) -> PPM {
+        let mut ppm = PPM::new(self.height, self.width);
+        let scale = 255.0 / self.max_sample_count().max(1) as f64;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let level = (scale * self.pixel(x, y).sample_count as f64) as u8;
+                ppm.set_pixel(
+                    x,
+                    self.height - 1 - y,
+                    RGB {
+                        r: level,
+                        g: level,
+                        b: level,
+                    },
+                );
+            }
+        }
+        ppm
+    }
+
+    // Colors of every pixel, in the same order as `pixels`.
+    pub fn pixel_colors(&self) -> Vec<Color> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| self.pixel_color(x, y))
+            .collect()
+    }
+
+    pub fn to_ppm_region(&self, bounds: &PixelBounds, tone_mapping: &ToneMapping) -> PPM {
+        self.colors_to_ppm(&self.pixel_colors(), bounds, tone_mapping)
+    }
+
+    // `colors` hold a value for every pixel of the film, in the same order as `pixels`.
+    pub fn colors_to_ppm(
+        &self,
+        colors: &[Color],
+        bounds: &PixelBounds,
+        tone_mapping: &ToneMapping,
+    ) -> PPM {
+        let to_display = conversion_matrix(self.color_space, tone_mapping.display_space);
+        let mut ppm = PPM::new(bounds.height(), bounds.width());
+        for (x, y) in bounds.pixels() {
+            let color = colors[self.pixel_index(x, y)];
+            ppm.set_pixel(
+                x - bounds.x0,
+                bounds.y1 - 1 - y,
+                tone_mapping.encode(transform_color(&to_display, color)),
+            );
+        }
+        ppm
+    }
+}
+
+fn is_finite(color: &Color) -> bool {
+    color.x.is_finite() && color.y.is_finite() && color.z.is_finite()
+}
@@ -0,0 +1,189 @@
+use super::constants::*;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl FilterKind {
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        match name {
+            "box" => Some(FilterKind::Box),
+            "tent" => Some(FilterKind::Tent),
+            "gaussian" => Some(FilterKind::Gaussian),
+            "mitchell" => Some(FilterKind::Mitchell),
+            "lanczos" => Some(FilterKind::Lanczos),
+            _ => None,
+        }
+    }
+
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 2.0,
+        }
+    }
+
+    pub fn make(&self, radius: Option<f64>) -> Box<dyn Filter> {
+        let radius = radius.unwrap_or_else(|| self.default_radius());
+        match self {
+            FilterKind::Box => Box::new(BoxFilter { radius }),
+            FilterKind::Tent => Box::new(TentFilter { radius }),
+            FilterKind::Gaussian => Box::new(GaussianFilter::new(radius, 0.5)),
+            FilterKind::Mitchell => Box::new(MitchellFilter {
+                radius,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            }),
+            FilterKind::Lanczos => Box::new(LanczosFilter { radius, tau: 3.0 }),
+        }
+    }
+}
+
+// Filters are separable and evaluated at an offset (in pixels) from the pixel center.
+pub trait Filter {
+    fn radius(&self) -> f64;
+    fn evaluate_1d(&self, x: f64) -> f64;
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+}
+
+pub struct BoxFilter {
+    radius: f64,
+}
+
+pub struct TentFilter {
+    radius: f64,
+}
+
+pub struct GaussianFilter {
+    radius: f64,
+    sigma: f64,
+    exp_radius: f64,
+}
+
+pub struct MitchellFilter {
+    radius: f64,
+    b: f64,
+    c: f64,
+}
+
+pub struct LanczosFilter {
+    radius: f64,
+    tau: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, sigma: f64) -> GaussianFilter {
+        GaussianFilter {
+            radius,
+            sigma,
+            exp_radius: gaussian(radius, sigma),
+        }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    // Half-open so a sample on a pixel boundary lands in exactly one pixel.
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        if x >= -self.radius && x < self.radius {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        (self.radius - x.abs()).max(0.0)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        (gaussian(x, self.sigma) - self.exp_radius).max(0.0)
+    }
+}
+
+impl MitchellFilter {
+    fn mitchell_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        let (b, c) = (self.b, self.c);
+        if x <= 1.0 {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b))
+                / 6.0
+        } else if x <= 2.0 {
+            ((-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c))
+                / 6.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        self.mitchell_1d(2.0 * x / self.radius)
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs() / self.radius;
+        if x > 1.0 {
+            0.0
+        } else {
+            windowed_sinc(x * self.tau, self.tau)
+        }
+    }
+}
+
+fn gaussian(x: f64, sigma: f64) -> f64 {
+    (-x * x / (2.0 * sigma * sigma)).exp() / (2.0 * PI * sigma * sigma).sqrt()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn windowed_sinc(x: f64, tau: f64) -> f64 {
+    sinc(x) * sinc(x / tau)
+}
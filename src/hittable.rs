@@ -1,7 +1,9 @@
 use super::aabb::*;
+use super::color::*;
 use super::material::*;
 use super::normal::*;
 use super::ray::*;
+use super::texture::*;
 use super::uv::*;
 use super::vec3::*;
 
@@ -13,6 +15,13 @@ pub struct HitRecord<'a> {
     pub material: &'a dyn Material,
     pub u: f64,
     pub v: f64,
+    // Address of the primitive that was hit; only meaningful within one process.
+    pub object_id: usize,
+    // Factor applied to the path's throughput on reaching the hit; only media that track
+    // chromatic extinction set it to anything but white.
+    pub weight: Color,
+    // Set by the renderer at the hits of camera rays, for texture filtering.
+    pub footprint: Option<UvFootprint>,
 }
 
 impl<'a> HitRecord<'a> {
@@ -29,10 +38,21 @@ impl<'a> HitRecord<'a> {
             normal: normal,
             front_face: front_facing,
             material: entity.material(),
-            u: entity.get_u(outward_normal),
-            v: entity.get_v(outward_normal),
+            u: entity.get_u(hit_point, outward_normal),
+            v: entity.get_v(hit_point, outward_normal),
+            object_id: entity as *const T as usize,
+            weight: Color::new(1.0, 1.0, 1.0),
+            footprint: None,
         }
     }
+
+    // Whether the surface is there, rather than a hole in the opacity mask of its
+    // material; primitives look further along the ray past holes.
+    pub fn is_opaque(&self, ray: &Ray) -> bool {
+        self.material
+            .opacity()
+            .is_none_or(|mask| mask.covers(ray, self.u, self.v, &self.p))
+    }
 }
 
 pub trait Hittable {
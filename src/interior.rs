@@ -0,0 +1,61 @@
+use super::color::*;
+use super::material::*;
+
+// Deeper nesting is not tracked: further dielectrics are treated as if entered from the
+// innermost tracked one.
+const MAX_NESTED: usize = 4;
+
+// A dielectric a ray is inside of.
+#[derive(Copy, Clone)]
+pub struct Interior {
+    // The object's `HitRecord::object_id`; objects sharing a material are still told
+    // apart.
+    pub id: usize,
+    pub priority: u32,
+    pub ior: RefractiveIndex,
+    // Fraction of light absorbed per unit distance, per channel.
+    pub absorption: Color,
+}
+
+// The dielectrics enclosing a ray, in the order they were entered. Where they overlap,
+// the one with the highest priority is the medium the ray travels in, the most recently
+// entered one on ties.
+#[derive(Copy, Clone, Default)]
+pub struct InteriorStack {
+    entries: [Option<Interior>; MAX_NESTED],
+}
+
+impl InteriorStack {
+    pub fn current(&self) -> Option<&Interior> {
+        self.entries
+            .iter()
+            .flatten()
+            .max_by_key(|interior| interior.priority)
+    }
+
+    pub fn push(&mut self, interior: Interior) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.is_none()) {
+            *entry = Some(interior);
+        }
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        let mut remaining = InteriorStack::default();
+        for interior in self.entries.iter().flatten() {
+            if interior.id != id {
+                remaining.push(*interior);
+            }
+        }
+        *self = remaining;
+    }
+
+    // Beer-Lambert attenuation over `distance` in the current medium.
+    pub fn transmittance(&self, distance: f64) -> Color {
+        match self.current() {
+            Some(interior) => interior
+                .absorption
+                .map(|absorption| (-absorption * distance).exp()),
+            None => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+}
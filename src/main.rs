@@ -2,74 +2,142 @@ extern crate num;
 
 mod aabb;
 mod angles;
+mod aov;
+mod binio;
 mod bvh;
 mod camera;
+mod checkpoint;
 mod color;
+mod colorspace;
 mod constants;
 mod cube;
+mod denoise;
+mod distributed;
+mod exr;
+mod film;
+mod filter;
 mod hittable;
+mod interior;
 mod material;
 mod normal;
+mod options;
 mod perlin;
+mod phase;
 mod ppm;
+mod progress;
 mod random;
 mod ray;
 mod rectangle;
+mod render;
+mod sampler;
+mod sparse;
+mod spectrum;
 mod sphere;
+mod stats;
+mod subsurface;
 mod texture;
+mod tile;
+mod tonemap;
 mod transform;
 mod uv;
 mod vec3;
 mod volume;
 mod world;
 
+use std::time::Instant;
+
+use aov::*;
 use bvh::*;
 use camera::*;
+use checkpoint::*;
 use color::*;
-use constants::*;
-use hittable::*;
+use denoise::*;
+use distributed::*;
+use film::*;
 use material::*;
-use ppm::*;
+use options::*;
+use progress::*;
 use random::*;
-use ray::*;
+use render::*;
+use sparse::*;
+use spectrum::*;
+use stats::*;
+use subsurface::*;
 use texture::*;
+use tile::*;
 use vec3::*;
+use volume::*;
 use world::*;
 
-fn ray_color(ray: &Ray, background: &Color, hittable: &dyn Hittable, depth: i32) -> Vec3 {
-    if depth <= 0 {
-        Default::default()
-    } else if let Some(record) = hittable.hit(ray, 0.0001, INFINITY) {
-        let mut scattered = Default::default();
-        let mut attenuation = Default::default();
-        let emitted = record.material.emitted(record.u, record.v, &record.p);
-        if record
-            .material
-            .scatter(&ray, &record, &mut attenuation, &mut scattered)
-        {
-            emitted + attenuation * ray_color(&scattered, background, hittable, depth - 1)
-        } else {
-            emitted
-        }
+// Snapshots may be read while the render is still running, so the image is written next
+// to its destination and renamed into place.
+fn write_image(film: &Film, bounds: &PixelBounds, options: &Options) {
+    let temporary = format!("{}.tmp", options.output);
+    let ppm = if options.denoise {
+        let settings = DenoiseSettings {
+            iterations: options.denoise_iterations,
+        };
+        film.colors_to_ppm(&denoise(film, &settings), bounds, &options.tone_mapping)
     } else {
-        *background
-    }
+        film.to_ppm_region(bounds, &options.tone_mapping)
+    };
+    ppm.write_file(&temporary).expect("Cannot write ppm file!!");
+    std::fs::rename(&temporary, &options.output).expect("Cannot write ppm file!!");
 }
 
-fn gamma_correction(color: Vec3, samples_per_pixel: i32) -> RGB {
-    let scale = 1.0 / samples_per_pixel as f64;
-    let r_scaled = (scale * color.x).sqrt();
-    let g_scaled = (scale * color.y).sqrt();
-    let b_scaled = (scale * color.z).sqrt();
-    RGB {
-        r: (256.0 * num::clamp(r_scaled, 0.0, 0.999)) as u8,
-        g: (256.0 * num::clamp(g_scaled, 0.0, 0.999)) as u8,
-        b: (256.0 * num::clamp(b_scaled, 0.0, 0.999)) as u8,
+fn report_stats(options: &Options, start: Instant) {
+    let mut stats = take_counters();
+    stats.elapsed = start.elapsed();
+    if options.stats {
+        eprintln!("{}", stats.summary());
+    }
+    if let Some(filename) = &options.stats_json {
+        stats
+            .write_json(filename)
+            .expect("Cannot write statistics file!!");
     }
 }
 
 fn main() {
-    let mut ppm = PPM::new(200, 200);
+    let options = match Options::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, Options::usage());
+            std::process::exit(1);
+        }
+    };
+    let mut film = Film::new(
+        IMAGE_WIDTH,
+        IMAGE_HEIGHT,
+        options.filter.make(options.filter_radius),
+        options.working_space,
+    );
+    if options.aov.is_some() || options.denoise {
+        film.enable_aovs();
+    }
+    let bounds = options.render_bounds(film.width, film.height);
+    let fingerprint = options.fingerprint(film.width, film.height);
+    let output_bounds = if options.write_cropped {
+        bounds
+    } else {
+        PixelBounds::new(0, 0, film.width, film.height)
+    };
+    let progress = Progress::new(options.progress);
+    if let Some(address) = &options.serve {
+        let tiles = make_tiles(&bounds, options.tile_size, options.tile_order);
+        run_coordinator(
+            address,
+            &fingerprint,
+            &mut film,
+            tiles,
+            options.samples,
+            &progress,
+        )
+        .expect("Cannot run coordinator!!");
+        progress.finish();
+        write_image(&film, &output_bounds, &options);
+        return;
+    }
     let look_from = Vec3::new(478.0, 278.0, -600.0);
     let look_at = Vec3::new(278.0, 278.0, 0.0);
     let dist_to_focus = 10.0;
@@ -78,30 +146,179 @@ fn main() {
         look_at,
         Vec3::new(0.0, 1.0, 0.0),
         40.0,
-        ppm.width as f64 / ppm.height as f64,
+        film.width as f64 / film.height as f64,
         0.0,
         dist_to_focus,
         0.0,
         1.0,
     );
-    let textures = Textures::new();
-    let materials = Materials::new(&textures);
-    let world = World::new_final_scene(&materials);
-    let samples = 10;
-    let max_depth = 10;
-    let background = Color::new(0.0, 0.0, 0.0);
-
-    for j in 0..ppm.height {
-        for i in 0..ppm.width {
-            let mut c = Vec3::new(0.0, 0.0, 0.0);
-            for _ in 0..samples {
-                let u = (i as f64 + random_double()) / ppm.width as f64;
-                let v = (j as f64 + random_double()) / ppm.height as f64;
-                let ray = camera.get_ray(u, v);
-                c += ray_color(&ray, &background, &world, max_depth);
+    seed_random(options.seed);
+    let mut textures = Textures::new(options.working_space, options.texture_sampling)
+        .expect("Cannot load textures!!");
+    let cutout_texture = options.cutout.as_ref().map(|filename| {
+        textures.v_image_textures.push(
+            ImageTexture::new(filename, options.working_space, options.texture_sampling)
+                .expect("Cannot load cutout image!!"),
+        );
+        textures.v_image_textures.len() - 1
+    });
+    let mut materials = Materials::new(&textures, options.working_space);
+    if let Some(index) = cutout_texture {
+        let image = &textures.v_image_textures[index];
+        materials.v_cutouts.push(Lambertian {
+            albedo: image,
+            opacity: Some(OpacityMask {
+                texture: image,
+                mode: options.cutout_alpha,
+            }),
+        });
+    }
+    materials.v_dielectrics[0] = Dielectric::new(options.glass, options.glass_absorption, 0);
+    if let Some(inclusion) = options.glass_inclusion {
+        materials
+            .v_dielectrics
+            .push(Dielectric::new(inclusion, Color::default(), 1));
+    }
+    if let Some(phase) = options.phase {
+        materials
+            .v_anisotropics
+            .push(Anisotropic::new(&textures.v_solid_colors[3], phase));
+    }
+    if let Some(temperature) = options.light_temperature {
+        materials.v_blackbody_lights.push(BlackbodyLight::new(
+            temperature,
+            7.0,
+            options.working_space,
+        ));
+    }
+    let volume = options
+        .volume
+        .as_ref()
+        .map(|filename| SparseGrid::read(filename).expect("Cannot read volume file!!"));
+    let medium = if options.medium.is_some() || volume.is_some() {
+        Some(MediumSettings {
+            // A volume brings its own density.
+            density: options.medium.unwrap_or(DensityKind::Uniform),
+            extinction: options.medium_extinction,
+            albedo: options.medium_albedo,
+            emission: options.medium_emission,
+        })
+    } else {
+        None
+    };
+    let subsurface = options.subsurface.map(|albedo| SubsurfaceSettings {
+        albedo,
+        mean_free_path: options.subsurface_mean_free_path,
+        ior: options.subsurface_ior,
+    });
+    let world = World::new_final_scene(&materials, medium, volume, subsurface);
+    let renderer = Renderer {
+        camera: &camera,
+        world: &world,
+        background: Color::new(0.0, 0.0, 0.0),
+        max_depth: 10,
+        seed: options.seed,
+        tiles: make_tiles(&bounds, options.tile_size, options.tile_order),
+        progress: &progress,
+        mode: options.mode,
+        debug_max: options.debug_max,
+        spectral: if options.spectral {
+            Some(SpectralConversion::new(options.working_space))
+        } else {
+            None
+        },
+        ray_differentials: options.texture_sampling.mip_filter != MipFilter::None,
+    };
+    let start = Instant::now();
+    if let Some(address) = &options.worker {
+        let make_film = || {
+            Film::new(
+                film.width,
+                film.height,
+                options.filter.make(options.filter_radius),
+                options.working_space,
+            )
+        };
+        run_worker(
+            address,
+            &fingerprint,
+            &renderer,
+            &make_film,
+            options.sampler,
+            options.seed,
+        )
+        .expect("Worker failed!!");
+        report_stats(&options, start);
+        return;
+    }
+    if let Some(filename) = &options.checkpoint {
+        if options.resume {
+            read_checkpoint(filename, &fingerprint, &mut film).expect("Cannot resume checkpoint!!");
+            eprintln!(
+                "resumed {} at {} samples per pixel",
+                filename,
+                renderer.min_sample_count(&film)
+            );
+        }
+        install_interrupt_handler();
+    }
+    let mut snapshots = PassSchedule::new(options.snapshot_passes, options.snapshot_seconds);
+    let mut checkpoints = PassSchedule::new(None, options.checkpoint_seconds);
+    let mut after_pass = |film: &Film| {
+        if options.progressive && snapshots.due() {
+            write_image(film, &output_bounds, &options);
+            eprintln!(
+                "snapshot at {} samples per pixel",
+                renderer.min_sample_count(film)
+            );
+        }
+        if let Some(filename) = &options.checkpoint {
+            if checkpoints.due() || interrupted() {
+                write_checkpoint(filename, &fingerprint, film).expect("Cannot write checkpoint!!");
             }
-            ppm.set_pixel(i, ppm.height - j, gamma_correction(c, samples));
         }
+        !interrupted()
+    };
+
+    if options.adaptive {
+        let settings = AdaptiveSettings {
+            min_samples: options.min_samples,
+            max_samples: options.max_samples,
+            threshold: options.adaptive_threshold,
+        };
+        let mut sampler = options.sampler.make(settings.max_samples, options.seed);
+        renderer.render_adaptive(&mut film, sampler.as_mut(), &settings, &mut after_pass);
+    } else {
+        let pass_samples = if options.progressive || options.checkpoint.is_some() {
+            options.pass_samples
+        } else {
+            options.samples
+        };
+        let mut sampler = options.sampler.make(options.samples, options.seed);
+        renderer.render_progressive(
+            &mut film,
+            sampler.as_mut(),
+            options.samples,
+            pass_samples,
+            &mut after_pass,
+        );
+    }
+    progress.finish();
+    if let Some(filename) = &options.checkpoint {
+        if !interrupted() {
+            write_checkpoint(filename, &fingerprint, &film).expect("Cannot write checkpoint!!");
+        }
+    }
+    report_stats(&options, start);
+    write_image(&film, &output_bounds, &options);
+    if let Some(filename) = &options.aov {
+        aov_image(&film, &output_bounds)
+            .write(filename)
+            .expect("Cannot write AOV file!!");
+    }
+    if let Some(filename) = &options.sample_count_map {
+        film.sample_count_ppm()
+            .write_file(filename)
+            .expect("Cannot write sample count map!!");
     }
-    ppm.write_file("test.ppm").expect("Cannot write ppm file!!");
 }
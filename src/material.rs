@@ -1,15 +1,61 @@
 extern crate libm;
 
 use super::color::*;
+use super::colorspace::*;
 use super::hittable::*;
+use super::interior::*;
+use super::phase::*;
 use super::random::*;
 use super::ray::*;
+use super::sampler::*;
+use super::spectrum::*;
 use super::texture::*;
 use super::vec3::*;
 use libm::*;
 
 pub struct Lambertian<'a> {
     pub albedo: &'a dyn Texture,
+    pub opacity: Option<OpacityMask<'a>>,
+}
+
+// How the partial coverage of an opacity mask is resolved.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AlphaMode {
+    // Texels with alpha below the threshold are holes, the others are solid.
+    Threshold(f64),
+    // Rays go through in proportion to the transparency, which averages to soft edges.
+    // Whether a given ray does depends only on the ray and where it hits, so repeated
+    // queries agree.
+    Stochastic,
+}
+
+// Alpha of a texture cutting holes in a surface, as for leaves or fences on simple quads.
+pub struct OpacityMask<'a> {
+    pub texture: &'a dyn Texture,
+    pub mode: AlphaMode,
+}
+
+impl<'a> OpacityMask<'a> {
+    pub fn covers(&self, ray: &Ray, u: f64, v: f64, point: &Vec3) -> bool {
+        let alpha = self.texture.alpha(u, v, point);
+        match self.mode {
+            AlphaMode::Threshold(threshold) => alpha >= threshold,
+            AlphaMode::Stochastic => {
+                let bits = hash(&[
+                    ray.origin.x.to_bits(),
+                    ray.origin.y.to_bits(),
+                    ray.origin.z.to_bits(),
+                    ray.direction.x.to_bits(),
+                    ray.direction.y.to_bits(),
+                    ray.direction.z.to_bits(),
+                    u.to_bits(),
+                    v.to_bits(),
+                ]);
+                // The top 53 bits, as a number in [0, 1).
+                alpha > (bits >> 11) as f64 / (1u64 << 53) as f64
+            }
+        }
+    }
 }
 
 pub struct Metal {
@@ -17,24 +63,56 @@ pub struct Metal {
     fuzz: f64,
 }
 
+// Index of refraction as a function of the wavelength. Cauchy and Sellmeier
+// coefficients take the wavelength in micrometers.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RefractiveIndex {
+    Constant(f64),
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+// Glass-like material. Light inside is absorbed per unit distance by `absorption`.
+// Overlapping dielectrics are resolved by priority: inside a higher priority one the
+// surfaces of lower priority ones are ignored.
 pub struct Dielectric {
-    ref_idx: f64,
+    ior: RefractiveIndex,
+    absorption: Color,
+    priority: u32,
 }
 
 pub struct DiffuseLight<'a> {
     emit: &'a dyn Texture,
 }
 
+// Emits the spectrum of a black body at `temperature` kelvin, scaled to `luminance`.
+// RGB renders use the color of that spectrum.
+pub struct BlackbodyLight {
+    temperature: f64,
+    scale: f64,
+    color: Color,
+}
+
 pub struct Isotropic<'a> {
     albedo: &'a dyn Texture,
 }
 
+// Scatters in a medium following a phase function instead of uniformly.
+pub struct Anisotropic<'a> {
+    albedo: &'a dyn Texture,
+    phase: PhaseFunction,
+}
+
 pub struct Materials<'a> {
     pub v_lambertians: std::vec::Vec<Lambertian<'a>>,
     pub v_metals: std::vec::Vec<Metal>,
     pub v_dielectrics: std::vec::Vec<Dielectric>,
     pub v_isotropics: std::vec::Vec<Isotropic<'a>>,
+    pub v_anisotropics: std::vec::Vec<Anisotropic<'a>>,
     pub v_diffuse_lights: std::vec::Vec<DiffuseLight<'a>>,
+    pub v_blackbody_lights: std::vec::Vec<BlackbodyLight>,
+    // Surfaces with an opacity mask.
+    pub v_cutouts: std::vec::Vec<Lambertian<'a>>,
 }
 
 impl Metal {
@@ -46,34 +124,68 @@ impl Metal {
     }
 }
 
+impl BlackbodyLight {
+    pub fn new(temperature: f64, luminance: f64, working_space: ColorSpace) -> BlackbodyLight {
+        let conversion = SpectralConversion::new(working_space);
+        let (color, spectrum_luminance) =
+            conversion.spectrum_color(|lambda| blackbody(lambda, temperature));
+        let scale = luminance / spectrum_luminance;
+        BlackbodyLight {
+            temperature,
+            scale,
+            color: scale * color,
+        }
+    }
+}
+
+impl<'a> Anisotropic<'a> {
+    pub fn new(albedo: &'a dyn Texture, phase: PhaseFunction) -> Anisotropic<'a> {
+        Anisotropic { albedo, phase }
+    }
+}
+
 impl<'a> Materials<'a> {
-    pub fn new(textures: &'a Textures) -> Materials<'a> {
+    // Colors below are linear sRGB and converted to the working color space.
+    pub fn new(textures: &'a Textures, working_space: ColorSpace) -> Materials<'a> {
+        let to_working = conversion_matrix(ColorSpace::Srgb, working_space);
         let mut materials = Materials {
             v_lambertians: vec![
                 Lambertian {
                     albedo: &textures.v_solid_colors[0],
+                    opacity: None,
                 },
                 Lambertian {
                     albedo: &textures.v_solid_colors[2],
+                    opacity: None,
                 },
                 Lambertian {
                     albedo: &textures.v_image_textures[0],
+                    opacity: None,
                 },
                 Lambertian {
                     albedo: &textures.v_noise_textures[0],
+                    opacity: None,
                 },
                 Lambertian {
                     albedo: &textures.v_solid_colors[5],
+                    opacity: None,
                 },
             ],
             v_metals: vec![Metal {
-                albedo: Vec3::new(0.8, 0.8, 0.9),
+                albedo: transform_color(&to_working, Vec3::new(0.8, 0.8, 0.9)),
                 fuzz: 1.0,
             }],
-            v_dielectrics: vec![Dielectric { ref_idx: 1.5 }],
+            v_dielectrics: vec![Dielectric::new(
+                RefractiveIndex::Constant(1.5),
+                Color::default(),
+                0,
+            )],
             v_diffuse_lights: vec![DiffuseLight {
                 emit: &textures.v_solid_colors[1],
             }],
+            v_blackbody_lights: Vec::new(),
+            v_cutouts: Vec::new(),
+            v_anisotropics: Vec::new(),
             v_isotropics: vec![
                 Isotropic {
                     albedo: &textures.v_solid_colors[3],
@@ -89,9 +201,10 @@ impl<'a> Materials<'a> {
                     random_integer_in_limit(0, textures.v_solid_colors.len() - 1);
                 materials.v_lambertians.push(Lambertian {
                     albedo: &textures.v_solid_colors[random_textures_index],
+                    opacity: None,
                 });
                 materials.v_metals.push(Metal::new(
-                    random_color_in_limit(0.5, 1.0),
+                    transform_color(&to_working, random_color_in_limit(0.5, 1.0)),
                     random_double_in_limit(0.0, 0.5),
                 ));
             }
@@ -107,10 +220,29 @@ pub trait Material {
         record: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool;
     fn emitted(&self, u: f64, v: f64, point: &Vec3) -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
+    // Emission at the wavelengths of a spectral path; by default the RGB emission is
+    // upsampled.
+    fn emitted_spectrum(
+        &self,
+        u: f64,
+        v: f64,
+        point: &Vec3,
+        wavelengths: &SampledWavelengths,
+        conversion: &SpectralConversion,
+    ) -> SampledSpectrum {
+        conversion.upsample(self.emitted(u, v, point), wavelengths)
+    }
+    // Base color of the surface, for debug output.
+    fn albedo(&self, u: f64, v: f64, point: &Vec3) -> Color;
+    // Mask primitives consult to let rays through holes in the surface.
+    fn opacity(&self) -> Option<&OpacityMask<'_>> {
+        None
+    }
 }
 
 pub trait MaterialOp {
@@ -124,12 +256,25 @@ impl<'a> Material for Lambertian<'a> {
         record: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool {
-        let scatter_direction = record.normal + random_in_unit_sphere();
+        let scatter_direction =
+            record.normal + sample_in_unit_sphere(sampler.get_2d(), sampler.get_1d());
         *scattered = Ray::new(record.p, scatter_direction, ray_in.time);
-        *attenuation = self.albedo.value(record.u, record.v, &record.p);
+        *attenuation = match &record.footprint {
+            Some(footprint) => self
+                .albedo
+                .filtered_value(record.u, record.v, footprint, &record.p),
+            None => self.albedo.value(record.u, record.v, &record.p),
+        };
         true
     }
+    fn albedo(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        self.albedo.value(u, v, point)
+    }
+    fn opacity(&self) -> Option<&OpacityMask<'_>> {
+        self.opacity.as_ref()
+    }
 }
 
 impl Material for Metal {
@@ -139,20 +284,75 @@ impl Material for Metal {
         record: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool {
         let reflected = ray_in.direction.unit_vector().reflect(record.normal);
         *scattered = Ray::new(
             record.p,
-            reflected + self.fuzz * random_in_unit_sphere(),
+            reflected + self.fuzz * sample_in_unit_sphere(sampler.get_2d(), sampler.get_1d()),
             ray_in.time,
         );
         *attenuation = self.albedo;
         scattered.direction.dot(&record.normal) > 0.0
     }
+    fn albedo(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        self.albedo
+    }
+}
+
+// Wavelength at which dispersive glass is evaluated when tracing RGB: the helium d line
+// that catalog indices are quoted for.
+pub const D_LINE: f64 = 587.56;
+
+impl RefractiveIndex {
+    pub fn from_name(name: &str) -> Option<RefractiveIndex> {
+        match name {
+            "bk7" => Some(RefractiveIndex::Sellmeier {
+                b: [1.03961212, 0.231792344, 1.01046945],
+                c: [0.00600069867, 0.0200179144, 103.560653],
+            }),
+            "fused-silica" => Some(RefractiveIndex::Sellmeier {
+                b: [0.6961663, 0.4079426, 0.8974794],
+                c: [0.00467914826, 0.0135120631, 97.9340025],
+            }),
+            "diamond" => Some(RefractiveIndex::Sellmeier {
+                b: [0.3306, 4.3356, 0.0],
+                c: [0.030625, 0.011236, 0.0],
+            }),
+            _ => None,
+        }
+    }
+
+    // `lambda` in nanometers.
+    pub fn at(&self, lambda: f64) -> f64 {
+        let micrometers = lambda / 1000.0;
+        let squared = micrometers * micrometers;
+        match self {
+            RefractiveIndex::Constant(index) => *index,
+            RefractiveIndex::Cauchy { a, b } => a + b / squared,
+            RefractiveIndex::Sellmeier { b, c } => (1.0
+                + (0..3)
+                    .map(|i| b[i] * squared / (squared - c[i]))
+                    .sum::<f64>())
+            .sqrt(),
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, RefractiveIndex::Constant(_))
+    }
 }
 
 impl Dielectric {
-    fn schlick(cosine: f64, ref_idx: f64) -> f64 {
+    pub fn new(ior: RefractiveIndex, absorption: Color, priority: u32) -> Dielectric {
+        Dielectric {
+            ior,
+            absorption,
+            priority,
+        }
+    }
+
+    pub fn schlick(cosine: f64, ref_idx: f64) -> f64 {
         let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
         let r0_squared = r0 * r0;
         r0_squared + (1.0 - r0_squared) * (1.0 - cosine).powi(5)
@@ -166,28 +366,75 @@ impl Material for Dielectric {
         record: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool {
         *attenuation = Vec3::new(1.0, 1.0, 1.0);
+        let this = Interior {
+            id: record.object_id,
+            priority: self.priority,
+            ior: self.ior,
+            absorption: self.absorption,
+        };
+        let mut interiors = ray_in.interiors.unwrap_or_default();
+        // The enclosing dielectrics once the ray has crossed this surface.
+        let mut crossed = interiors;
+        if record.front_face {
+            crossed.push(this);
+        } else {
+            crossed.remove(this.id);
+        }
+        // Surfaces inside a higher priority dielectric only change the medium stack.
+        let outer = if record.front_face {
+            interiors.current()
+        } else {
+            crossed.current()
+        };
+        if outer.is_some_and(|outer| outer.priority > self.priority) {
+            *scattered = Ray::new(record.p, ray_in.direction, ray_in.time);
+            scattered.interiors = Some(crossed);
+            return true;
+        }
+        let outer_ior = outer.map(|outer| outer.ior);
+        // Dispersion bends every wavelength differently, so spectral paths continue with
+        // the hero wavelength only.
+        let mut wavelengths = ray_in.wavelengths;
+        let dispersive =
+            self.ior.is_dispersive() || outer_ior.is_some_and(|ior| ior.is_dispersive());
+        let lambda = match &mut wavelengths {
+            Some(wavelengths) if dispersive => {
+                wavelengths.terminate_secondary();
+                wavelengths.hero()
+            }
+            _ => D_LINE,
+        };
+        let ref_idx = self.ior.at(lambda) / outer_ior.map_or(1.0, |ior| ior.at(lambda));
         let etai_over_etat = if record.front_face {
-            1.0 / self.ref_idx
+            1.0 / ref_idx
         } else {
-            self.ref_idx
+            ref_idx
         };
         let unit_direction = ray_in.direction.unit_vector();
         let cos_theta = fmin((-unit_direction).dot(&record.normal), 1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let reflect_sample = sampler.get_1d();
         if etai_over_etat * sin_theta > 1.0 {
             let reflected = unit_direction.reflect(record.normal);
             *scattered = Ray::new(record.p, reflected, ray_in.time);
-        } else if Self::schlick(cos_theta, etai_over_etat) > random_double() {
+        } else if Self::schlick(cos_theta, etai_over_etat) > reflect_sample {
             let reflected = unit_direction.reflect(record.normal);
             *scattered = Ray::new(record.p, reflected, ray_in.time);
         } else {
             let refracted = Vec3::refract(unit_direction, record.normal, etai_over_etat);
             *scattered = Ray::new(record.p, refracted, ray_in.time);
+            interiors = crossed;
         }
+        scattered.wavelengths = wavelengths;
+        scattered.interiors = Some(interiors);
         true
     }
+    fn albedo(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
 }
 
 impl<'a> Material for DiffuseLight<'a> {
@@ -197,12 +444,47 @@ impl<'a> Material for DiffuseLight<'a> {
         _record: &HitRecord,
         _attenuation: &mut Vec3,
         _scattered: &mut Ray,
+        _sampler: &mut dyn Sampler,
     ) -> bool {
         false
     }
     fn emitted(&self, u: f64, v: f64, point: &Vec3) -> Color {
         self.emit.value(u, v, point)
     }
+    fn albedo(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        self.emit.value(u, v, point)
+    }
+}
+
+impl Material for BlackbodyLight {
+    fn scatter(
+        &self,
+        _ray_in: &Ray,
+        _record: &HitRecord,
+        _attenuation: &mut Vec3,
+        _scattered: &mut Ray,
+        _sampler: &mut dyn Sampler,
+    ) -> bool {
+        false
+    }
+    fn emitted(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        self.color
+    }
+    fn emitted_spectrum(
+        &self,
+        _u: f64,
+        _v: f64,
+        _point: &Vec3,
+        wavelengths: &SampledWavelengths,
+        _conversion: &SpectralConversion,
+    ) -> SampledSpectrum {
+        SampledSpectrum::from_fn(wavelengths, |lambda| {
+            self.scale * blackbody(lambda, self.temperature)
+        })
+    }
+    fn albedo(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        self.color
+    }
 }
 
 impl<'a> Material for Isotropic<'a> {
@@ -212,9 +494,42 @@ impl<'a> Material for Isotropic<'a> {
         record: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool {
-        *scattered = Ray::new(record.p, random_in_unit_sphere(), ray_in.time);
+        *scattered = Ray::new(
+            record.p,
+            sample_in_unit_sphere(sampler.get_2d(), sampler.get_1d()),
+            ray_in.time,
+        );
         *attenuation = self.albedo.value(record.u, record.v, &record.p);
         return true;
     }
+    fn albedo(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        self.albedo.value(u, v, point)
+    }
+}
+
+impl<'a> Material for Anisotropic<'a> {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        let (direction, pdf) =
+            self.phase
+                .sample(&ray_in.direction, sampler.get_2d(), sampler.get_1d());
+        // A direction the lobe cannot produce carries no light.
+        if pdf <= 0.0 {
+            return false;
+        }
+        *scattered = Ray::new(record.p, direction, ray_in.time);
+        *attenuation = self.albedo.value(record.u, record.v, &record.p);
+        true
+    }
+    fn albedo(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        self.albedo.value(u, v, point)
+    }
 }
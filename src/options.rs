@@ -0,0 +1,719 @@
+use super::color::*;
+use super::colorspace::*;
+use super::filter::*;
+use super::material::*;
+use super::phase::*;
+use super::progress::*;
+use super::render::*;
+use super::sampler::*;
+use super::texture::*;
+use super::tile::*;
+use super::tonemap::*;
+use super::volume::*;
+
+// Size of the rendered image, in pixels.
+pub const IMAGE_WIDTH: u32 = 200;
+pub const IMAGE_HEIGHT: u32 = 200;
+
+pub struct Options {
+    pub samples: u32,
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    pub filter: FilterKind,
+    pub filter_radius: Option<f64>,
+    pub adaptive: bool,
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub adaptive_threshold: f64,
+    pub sample_count_map: Option<String>,
+    pub output: String,
+    pub progressive: bool,
+    pub pass_samples: u32,
+    pub snapshot_passes: Option<u32>,
+    pub snapshot_seconds: Option<f64>,
+    pub checkpoint: Option<String>,
+    pub checkpoint_seconds: Option<f64>,
+    pub resume: bool,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    pub crop: Option<Vec<f64>>,
+    pub region: Option<Vec<u32>>,
+    pub write_cropped: bool,
+    pub serve: Option<String>,
+    pub worker: Option<String>,
+    pub stats: bool,
+    pub stats_json: Option<String>,
+    pub progress: ProgressMode,
+    pub mode: RenderMode,
+    pub debug_max: f64,
+    pub aov: Option<String>,
+    pub denoise: bool,
+    pub denoise_iterations: u32,
+    pub tone_mapping: ToneMapping,
+    pub working_space: ColorSpace,
+    pub spectral: bool,
+    pub light_temperature: Option<f64>,
+    pub glass: RefractiveIndex,
+    pub glass_absorption: Color,
+    pub glass_inclusion: Option<RefractiveIndex>,
+    pub medium: Option<DensityKind>,
+    pub medium_extinction: Color,
+    pub medium_albedo: Color,
+    pub medium_emission: Color,
+    pub volume: Option<String>,
+    pub subsurface: Option<Color>,
+    pub subsurface_mean_free_path: Color,
+    pub subsurface_ior: f64,
+    pub phase: Option<PhaseFunction>,
+    pub texture_sampling: TextureSampling,
+    pub cutout: Option<String>,
+    pub cutout_alpha: AlphaMode,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            samples: 10,
+            sampler: SamplerKind::Independent,
+            seed: 0,
+            filter: FilterKind::Box,
+            filter_radius: None,
+            adaptive: false,
+            min_samples: 4,
+            max_samples: 64,
+            adaptive_threshold: 0.05,
+            sample_count_map: None,
+            output: "test.ppm".to_string(),
+            progressive: false,
+            pass_samples: 1,
+            snapshot_passes: None,
+            snapshot_seconds: None,
+            checkpoint: None,
+            checkpoint_seconds: None,
+            resume: false,
+            tile_size: 16,
+            tile_order: TileOrder::Scanline,
+            crop: None,
+            region: None,
+            write_cropped: false,
+            serve: None,
+            worker: None,
+            stats: false,
+            stats_json: None,
+            progress: ProgressMode::Human,
+            mode: RenderMode::Shaded,
+            debug_max: 1000.0,
+            aov: None,
+            denoise: false,
+            denoise_iterations: 5,
+            tone_mapping: Default::default(),
+            working_space: ColorSpace::Srgb,
+            spectral: false,
+            light_temperature: None,
+            glass: RefractiveIndex::Constant(1.5),
+            glass_absorption: Color::default(),
+            glass_inclusion: None,
+            medium: None,
+            medium_extinction: Color::new(0.2, 0.2, 0.2),
+            medium_albedo: Color::new(1.0, 1.0, 1.0),
+            medium_emission: Color::default(),
+            volume: None,
+            subsurface: None,
+            subsurface_mean_free_path: Color::new(5.0, 5.0, 5.0),
+            subsurface_ior: 1.4,
+            phase: None,
+            texture_sampling: Default::default(),
+            cutout: None,
+            cutout_alpha: AlphaMode::Threshold(0.5),
+        }
+    }
+}
+
+impl Options {
+    pub fn usage() -> &'static str {
+        "usage: rs-raytracer [options]
+  --samples <n>        samples per pixel (default 10)
+  --sampler <name>     independent | stratified | halton | sobol (default independent)
+  --seed <n>           seed for sample generation (default 0)
+  --filter <name>      box | tent | gaussian | mitchell | lanczos (default box)
+  --filter-radius <r>  pixel filter radius in pixels (default depends on the filter)
+  --adaptive           keep sampling only pixels whose estimated error is above the threshold
+  --min-samples <n>    adaptive: samples every pixel gets (default 4)
+  --max-samples <n>    adaptive: upper bound of samples per pixel (default 64)
+  --adaptive-threshold <e>
+                       adaptive: relative standard error to reach (default 0.05)
+  --spp-map <file>     write the per-pixel sample count map as a grayscale PPM
+  --output <file>      output image (default test.ppm)
+  --progressive        render the whole image in passes, writing intermediate snapshots
+  --pass-samples <n>   progressive: samples per pixel added by each pass (default 1)
+  --snapshot-passes <n>
+                       progressive: write a snapshot every n passes
+  --snapshot-seconds <s>
+                       progressive: write a snapshot every s seconds
+  --checkpoint <file>  save the film to this file at the end, on Ctrl-C and at intervals
+  --checkpoint-seconds <s>
+                       save a checkpoint every s seconds
+  --resume             continue from the checkpoint file up to the requested samples
+  --tile-size <n>      tile side in pixels (default 16)
+  --tile-order <name>  scanline | spiral | hilbert (default scanline)
+  --crop <x0,x1,y0,y1> render only this window, in fractions of the image from the top-left
+  --region <x,y,w,h>   render only this pixel rectangle, from the top-left
+  --write-cropped      write only the rendered window instead of the full-size image
+  --serve <addr>       coordinate a distributed render: hand tiles out to workers
+                       connecting to addr (e.g. 0.0.0.0:7878) and merge their results
+  --worker <addr>      render tiles for the coordinator at addr; scene settings (--seed,
+                       --sampler, --filter, ...) must match the coordinator's
+  --stats              print ray and intersection counters to stderr after the render;
+                       there are no shadow rays, lights are only found by path rays
+  --stats-json <file>  also write the counters as JSON
+  --progress <mode>    none | human | machine progress lines on stderr (default human)
+  --mode <name>        shaded | normals | front-face | depth | uv | albedo | material-id |
+                       object-id | bvh-heatmap (default shaded); every mode but shaded
+                       shows the first hit of the camera ray
+  --debug-max <v>      distance shown as white in depth mode and BVH node visits shown
+                       as red in bvh-heatmap mode (default 1000)
+  --aov <file>         also write an OpenEXR file with the beauty pass and emission, direct,
+                       indirect, albedo, normal, depth and object id layers
+  --denoise            denoise the output image, guided by the albedo, normal and depth
+  --denoise-iterations <n>
+                       denoise: wavelet filter passes, each twice as wide (default 5)
+  --tonemap <name>     clamp | reinhard | reinhard-extended | aces | hable | agx
+                       (default clamp)
+  --exposure <ev>      exposure adjustment in stops before tone mapping (default 0)
+  --white-point <l>    reinhard-extended: luminance mapped to white (default 4)
+  --working-space <name>
+                       srgb | rec2020 | acescg linear space to render in (default srgb)
+  --display-space <name>
+                       srgb | rec2020 space of the output image (default srgb)
+  --spectral           trace sampled wavelengths instead of RGB
+  --light-temperature <k>
+                       make the ceiling light a black body at k kelvin
+  --glass <ior>        index of refraction of the glass sphere: a number, bk7 |
+                       fused-silica | diamond, cauchy:a,b or sellmeier:b1,b2,b3,c1,c2,c3
+                       (micrometers; default 1.5); dispersion needs --spectral
+  --glass-absorption <r,g,b>
+                       absorption of light inside the glass sphere per unit distance
+  --glass-inclusion <ior>
+                       put a smaller sphere of this glass inside the glass sphere
+  --medium <density>   make the blue smoke ball a heterogeneous medium with uniform |
+                       noise | cloud density
+  --medium-extinction <r,g,b>
+                       medium: extinction per unit density (default 0.2,0.2,0.2)
+  --medium-albedo <r,g,b>
+                       medium: scattered fraction of the extinction (default 1,1,1)
+  --medium-emission <r,g,b>
+                       medium: radiance emitted where light is absorbed (default 0,0,0)
+  --volume <file>      replace the smoke ball with a sparse voxel grid in the format
+                       described in src/sparse.rs, placed by its own transform and
+                       shaded with the --medium-* settings
+  --subsurface <r,g,b> make the marble ball translucent with this albedo, as in skin,
+                       wax or marble
+  --subsurface-mfp <r,g,b>
+                       subsurface: mean free path per channel (default 5,5,5)
+  --subsurface-ior <n> subsurface: index of refraction of the surface (default 1.4)
+  --phase <function>   phase function of the smoke ball: isotropic, hg:g or
+                       double-hg:g1,g2,w with asymmetries in (-1, 1) and w the share of
+                       the first lobe (default isotropic)
+  --texture-filter <name>
+                       nearest | bilinear | bicubic reconstruction of the image texture
+                       (default nearest)
+  --texture-wrap <name>
+                       repeat | mirror | clamp texture coordinates outside [0, 1]
+                       (default clamp)
+  --texture-mipmap <name>
+                       none | trilinear | anisotropic filtering of the image texture over
+                       each pixel, using ray differentials (default none)
+  --texture-scale <su,sv>
+                       scale of the texture coordinates about the center (default 1,1)
+  --texture-rotation <deg>
+                       counterclockwise rotation of the texture coordinates about the
+                       center (default 0)
+  --texture-offset <ou,ov>
+                       offset of the texture coordinates (default 0,0)
+  --cutout <image>     put a quad in front of the scene showing this image, with holes
+                       where its alpha is low, as for foliage
+  --cutout-alpha <mode>
+                       cutout: threshold:t makes alpha below t a hole, stochastic lets
+                       rays through in proportion to the transparency (default
+                       threshold:0.5)"
+    }
+
+    // Settings a checkpoint must have been rendered with to be resumed. The sample targets
+    // are left out so that a finished render can be resumed with more samples.
+    pub fn fingerprint(&self, width: u32, height: u32) -> String {
+        format!(
+            "{}x{} seed={} sampler={:?} filter={:?} filter-radius={:?} adaptive={} mode={:?} debug-max={:?} working-space={:?} spectral={} light-temperature={:?} glass={:?} glass-absorption={:?} glass-inclusion={:?} medium={:?} medium-extinction={:?} medium-albedo={:?} medium-emission={:?} volume={:?} subsurface={:?} subsurface-mfp={:?} subsurface-ior={:?} phase={:?} texture={:?} cutout={:?} cutout-alpha={:?}",
+            width,
+            height,
+            self.seed,
+            self.sampler,
+            self.filter,
+            self.filter_radius,
+            self.adaptive,
+            self.mode,
+            self.debug_max,
+            self.working_space,
+            self.spectral,
+            self.light_temperature,
+            self.glass,
+            components(&self.glass_absorption),
+            self.glass_inclusion,
+            self.medium,
+            components(&self.medium_extinction),
+            components(&self.medium_albedo),
+            components(&self.medium_emission),
+            self.volume,
+            self.subsurface.as_ref().map(components),
+            components(&self.subsurface_mean_free_path),
+            self.subsurface_ior,
+            self.phase,
+            self.texture_sampling,
+            self.cutout,
+            self.cutout_alpha
+        )
+    }
+
+    // The rendered window in film coordinates, whose origin is at the bottom-left while
+    // --crop and --region are given from the top-left of the image.
+    pub fn render_bounds(&self, width: u32, height: u32) -> PixelBounds {
+        let image = PixelBounds::new(0, 0, width, height);
+        let (x0, x1, top, bottom) = if let Some(region) = &self.region {
+            (
+                region[0],
+                region[0].saturating_add(region[2]),
+                region[1],
+                region[1].saturating_add(region[3]),
+            )
+        } else if let Some(crop) = &self.crop {
+            (
+                (crop[0] * width as f64).ceil() as u32,
+                (crop[1] * width as f64).ceil() as u32,
+                (crop[2] * height as f64).ceil() as u32,
+                (crop[3] * height as f64).ceil() as u32,
+            )
+        } else {
+            (0, width, 0, height)
+        };
+        PixelBounds::new(
+            x0,
+            height.saturating_sub(bottom),
+            x1,
+            height.saturating_sub(top),
+        )
+        .intersect(&image)
+    }
+
+    pub fn from_args<I>(args: I) -> Result<Options, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--samples" => options.samples = parse_value(&arg, args.next())?,
+                "--sampler" => {
+                    let name = next_value(&arg, args.next())?;
+                    options.sampler = SamplerKind::from_name(&name)
+                        .ok_or_else(|| format!("unknown sampler '{}'", name))?;
+                }
+                "--seed" => options.seed = parse_value(&arg, args.next())?,
+                "--filter" => {
+                    let name = next_value(&arg, args.next())?;
+                    options.filter = FilterKind::from_name(&name)
+                        .ok_or_else(|| format!("unknown filter '{}'", name))?;
+                }
+                "--filter-radius" => options.filter_radius = Some(parse_value(&arg, args.next())?),
+                "--adaptive" => options.adaptive = true,
+                "--min-samples" => options.min_samples = parse_value(&arg, args.next())?,
+                "--max-samples" => options.max_samples = parse_value(&arg, args.next())?,
+                "--adaptive-threshold" => {
+                    options.adaptive_threshold = parse_value(&arg, args.next())?
+                }
+                "--spp-map" => options.sample_count_map = Some(next_value(&arg, args.next())?),
+                "--output" => options.output = next_value(&arg, args.next())?,
+                "--progressive" => options.progressive = true,
+                "--pass-samples" => options.pass_samples = parse_value(&arg, args.next())?,
+                "--snapshot-passes" => {
+                    options.snapshot_passes = Some(parse_value(&arg, args.next())?)
+                }
+                "--snapshot-seconds" => {
+                    options.snapshot_seconds = Some(parse_value(&arg, args.next())?)
+                }
+                "--checkpoint" => options.checkpoint = Some(next_value(&arg, args.next())?),
+                "--checkpoint-seconds" => {
+                    options.checkpoint_seconds = Some(parse_value(&arg, args.next())?)
+                }
+                "--resume" => options.resume = true,
+                "--tile-size" => options.tile_size = parse_value(&arg, args.next())?,
+                "--tile-order" => {
+                    let name = next_value(&arg, args.next())?;
+                    options.tile_order = TileOrder::from_name(&name)
+                        .ok_or_else(|| format!("unknown tile order '{}'", name))?;
+                }
+                "--crop" => options.crop = Some(parse_list(&arg, args.next(), 4)?),
+                "--region" => options.region = Some(parse_list(&arg, args.next(), 4)?),
+                "--write-cropped" => options.write_cropped = true,
+                "--serve" => options.serve = Some(next_value(&arg, args.next())?),
+                "--worker" => options.worker = Some(next_value(&arg, args.next())?),
+                "--stats" => options.stats = true,
+                "--stats-json" => options.stats_json = Some(next_value(&arg, args.next())?),
+                "--progress" => {
+                    let name = next_value(&arg, args.next())?;
+                    options.progress = ProgressMode::from_name(&name)
+                        .ok_or_else(|| format!("unknown progress mode '{}'", name))?;
+                }
+                "--mode" => {
+                    let name = next_value(&arg, args.next())?;
+                    options.mode = RenderMode::from_name(&name)
+                        .ok_or_else(|| format!("unknown render mode '{}'", name))?;
+                }
+                "--debug-max" => options.debug_max = parse_value(&arg, args.next())?,
+                "--aov" => options.aov = Some(next_value(&arg, args.next())?),
+                "--denoise" => options.denoise = true,
+                "--tonemap" => {
+                    let name = next_value(&arg, args.next())?;
+                    options.tone_mapping.operator = ToneMapper::from_name(&name)
+                        .ok_or_else(|| format!("unknown tone mapper '{}'", name))?;
+                }
+                "--working-space" => options.working_space = parse_color_space(&arg, args.next())?,
+                "--display-space" => {
+                    options.tone_mapping.display_space = parse_color_space(&arg, args.next())?
+                }
+                "--spectral" => options.spectral = true,
+                "--light-temperature" => {
+                    options.light_temperature = Some(parse_value(&arg, args.next())?)
+                }
+                "--glass" => options.glass = parse_refractive_index(&arg, args.next())?,
+                "--glass-absorption" => options.glass_absorption = parse_color(&arg, args.next())?,
+                "--glass-inclusion" => {
+                    options.glass_inclusion = Some(parse_refractive_index(&arg, args.next())?)
+                }
+                "--medium" => {
+                    let name = next_value(&arg, args.next())?;
+                    options.medium = Some(
+                        DensityKind::from_name(&name)
+                            .ok_or_else(|| format!("unknown medium density '{}'", name))?,
+                    );
+                }
+                "--medium-extinction" => {
+                    options.medium_extinction = parse_color(&arg, args.next())?
+                }
+                "--medium-albedo" => options.medium_albedo = parse_color(&arg, args.next())?,
+                "--medium-emission" => options.medium_emission = parse_color(&arg, args.next())?,
+                "--volume" => options.volume = Some(next_value(&arg, args.next())?),
+                "--subsurface" => options.subsurface = Some(parse_color(&arg, args.next())?),
+                "--subsurface-mfp" => {
+                    options.subsurface_mean_free_path = parse_color(&arg, args.next())?
+                }
+                "--subsurface-ior" => options.subsurface_ior = parse_value(&arg, args.next())?,
+                "--phase" => options.phase = parse_phase_function(&arg, args.next())?,
+                "--texture-filter" => {
+                    let name = next_value(&arg, args.next())?;
+                    options.texture_sampling.filter = TextureFilter::from_name(&name)
+                        .ok_or_else(|| format!("unknown texture filter '{}'", name))?;
+                }
+                "--texture-wrap" => {
+                    let name = next_value(&arg, args.next())?;
+                    options.texture_sampling.wrap = WrapMode::from_name(&name)
+                        .ok_or_else(|| format!("unknown texture wrap mode '{}'", name))?;
+                }
+                "--texture-mipmap" => {
+                    let name = next_value(&arg, args.next())?;
+                    options.texture_sampling.mip_filter = MipFilter::from_name(&name)
+                        .ok_or_else(|| format!("unknown texture mipmap filter '{}'", name))?;
+                }
+                "--texture-scale" => {
+                    let scale = parse_list(&arg, args.next(), 2)?;
+                    options.texture_sampling.scale = (scale[0], scale[1]);
+                }
+                "--texture-rotation" => {
+                    options.texture_sampling.rotation = parse_value(&arg, args.next())?
+                }
+                "--texture-offset" => {
+                    let offset = parse_list(&arg, args.next(), 2)?;
+                    options.texture_sampling.offset = (offset[0], offset[1]);
+                }
+                "--cutout" => options.cutout = Some(next_value(&arg, args.next())?),
+                "--cutout-alpha" => options.cutout_alpha = parse_alpha_mode(&arg, args.next())?,
+                "--exposure" => options.tone_mapping.exposure = parse_value(&arg, args.next())?,
+                "--white-point" => {
+                    options.tone_mapping.white_point = parse_value(&arg, args.next())?
+                }
+                "--denoise-iterations" => {
+                    options.denoise_iterations = parse_value(&arg, args.next())?
+                }
+                _ => return Err(format!("unknown argument '{}'", arg)),
+            }
+        }
+        if options.samples == 0 {
+            return Err("--samples must be at least 1".to_string());
+        }
+        if options.min_samples < 2 || options.max_samples < options.min_samples {
+            return Err("expected 2 <= --min-samples <= --max-samples".to_string());
+        }
+        if options.adaptive && options.progressive {
+            return Err("--adaptive and --progressive cannot be combined".to_string());
+        }
+        if !(options.adaptive_threshold > 0.0 && options.adaptive_threshold.is_finite()) {
+            return Err("--adaptive-threshold must be positive and finite".to_string());
+        }
+        if options.pass_samples == 0 || options.snapshot_passes == Some(0) {
+            return Err("--pass-samples and --snapshot-passes must be at least 1".to_string());
+        }
+        if options
+            .snapshot_seconds
+            .is_some_and(|seconds| !(seconds > 0.0 && seconds.is_finite()))
+        {
+            return Err("--snapshot-seconds must be positive and finite".to_string());
+        }
+        if options
+            .checkpoint_seconds
+            .is_some_and(|seconds| !(seconds > 0.0 && seconds.is_finite()))
+        {
+            return Err("--checkpoint-seconds must be positive and finite".to_string());
+        }
+        if options.tile_size == 0 {
+            return Err("--tile-size must be at least 1".to_string());
+        }
+        if let Some(crop) = &options.crop {
+            if crop[0] >= crop[1] || crop[2] >= crop[3] || crop.iter().any(|c| *c < 0.0 || *c > 1.0)
+            {
+                return Err("--crop expects 0 <= x0 < x1 <= 1 and 0 <= y0 < y1 <= 1".to_string());
+            }
+        }
+        if let Some(region) = &options.region {
+            if region[2] == 0 || region[3] == 0 {
+                return Err("--region width and height must be at least 1".to_string());
+            }
+            if region[0].checked_add(region[2]).is_none()
+                || region[1].checked_add(region[3]).is_none()
+            {
+                return Err("--region extends past the largest pixel coordinate".to_string());
+            }
+        }
+        if options.render_bounds(IMAGE_WIDTH, IMAGE_HEIGHT).is_empty() {
+            return Err(format!(
+                "--region and --crop must cover at least one pixel of the {}x{} image",
+                IMAGE_WIDTH, IMAGE_HEIGHT
+            ));
+        }
+        if (options.serve.is_some() || options.worker.is_some())
+            && (options.adaptive || options.progressive || options.checkpoint.is_some())
+        {
+            return Err(
+                "--serve and --worker cannot be combined with --adaptive, --progressive or --checkpoint"
+                    .to_string(),
+            );
+        }
+        if options.serve.is_some() && options.worker.is_some() {
+            return Err("--serve and --worker are exclusive".to_string());
+        }
+        if options.resume && options.checkpoint.is_none() {
+            return Err("--resume requires --checkpoint".to_string());
+        }
+        if options.filter_radius.is_some_and(|radius| radius <= 0.0) {
+            return Err("--filter-radius must be positive".to_string());
+        }
+        if !options.tone_mapping.display_space.is_display() {
+            return Err("--display-space must be srgb or rec2020".to_string());
+        }
+        if options.tone_mapping.white_point <= 0.0 {
+            return Err("--white-point must be positive".to_string());
+        }
+        if !options.tone_mapping.exposure.is_finite() {
+            return Err("--exposure must be finite".to_string());
+        }
+        if options
+            .light_temperature
+            .is_some_and(|temperature| temperature <= 0.0)
+        {
+            return Err("--light-temperature must be positive".to_string());
+        }
+        if options.glass.at(D_LINE) < 1.0
+            || options
+                .glass_inclusion
+                .is_some_and(|inclusion| inclusion.at(D_LINE) < 1.0)
+        {
+            return Err(
+                "--glass and --glass-inclusion must have an index of refraction of at least 1"
+                    .to_string(),
+            );
+        }
+        let is_negative = |color: &Color| color.x < 0.0 || color.y < 0.0 || color.z < 0.0;
+        if is_negative(&options.glass_absorption)
+            || is_negative(&options.medium_extinction)
+            || is_negative(&options.medium_emission)
+        {
+            return Err(
+                "--glass-absorption, --medium-extinction and --medium-emission cannot be negative"
+                    .to_string(),
+            );
+        }
+        if is_negative(&options.medium_albedo)
+            || options.medium_albedo.x > 1.0
+            || options.medium_albedo.y > 1.0
+            || options.medium_albedo.z > 1.0
+        {
+            return Err("--medium-albedo must be between 0 and 1".to_string());
+        }
+        if options.subsurface.is_some_and(|albedo| {
+            is_negative(&albedo) || albedo.x > 1.0 || albedo.y > 1.0 || albedo.z > 1.0
+        }) {
+            return Err("--subsurface albedo must be between 0 and 1".to_string());
+        }
+        let mean_free_path = &options.subsurface_mean_free_path;
+        if mean_free_path.x <= 0.0 || mean_free_path.y <= 0.0 || mean_free_path.z <= 0.0 {
+            return Err("--subsurface-mfp must be positive".to_string());
+        }
+        if options.subsurface_ior < 1.0 {
+            return Err("--subsurface-ior must be at least 1".to_string());
+        }
+        if options.texture_sampling.scale.0 == 0.0 || options.texture_sampling.scale.1 == 0.0 {
+            return Err("--texture-scale cannot be zero".to_string());
+        }
+        if let AlphaMode::Threshold(threshold) = options.cutout_alpha {
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err("--cutout-alpha threshold must be between 0 and 1".to_string());
+            }
+        }
+        if options.medium.is_some() && options.volume.is_some() {
+            return Err("--medium and --volume cannot be combined".to_string());
+        }
+        let valid_asymmetry = |g: f64| g > -1.0 && g < 1.0;
+        match options.phase {
+            Some(PhaseFunction::HenyeyGreenstein { g }) if !valid_asymmetry(g) => {
+                return Err("--phase asymmetry must be between -1 and 1".to_string());
+            }
+            Some(PhaseFunction::DoubleHenyeyGreenstein {
+                g_forward,
+                g_backward,
+                weight,
+            }) if !valid_asymmetry(g_forward)
+                || !valid_asymmetry(g_backward)
+                || !(0.0..=1.0).contains(&weight) =>
+            {
+                return Err(
+                    "--phase asymmetries must be between -1 and 1 and the weight between 0 and 1"
+                        .to_string(),
+                );
+            }
+            _ => {}
+        }
+        if options.debug_max <= 0.0 {
+            return Err("--debug-max must be positive".to_string());
+        }
+        if options.mode.is_process_local()
+            && (options.serve.is_some() || options.worker.is_some() || options.resume)
+        {
+            return Err(
+                "material-id and object-id modes cannot be distributed or resumed".to_string(),
+            );
+        }
+        if (options.aov.is_some() || options.denoise)
+            && (options.serve.is_some() || options.worker.is_some() || options.checkpoint.is_some())
+        {
+            return Err(
+                "--aov and --denoise cannot be combined with --serve, --worker or --checkpoint"
+                    .to_string(),
+            );
+        }
+        Ok(options)
+    }
+}
+
+fn parse_color_space(arg: &str, value: Option<String>) -> Result<ColorSpace, String> {
+    let name = next_value(arg, value)?;
+    ColorSpace::from_name(&name).ok_or_else(|| format!("unknown color space '{}'", name))
+}
+
+fn parse_phase_function(arg: &str, value: Option<String>) -> Result<Option<PhaseFunction>, String> {
+    let value = next_value(arg, value)?;
+    if value == "isotropic" {
+        return Ok(None);
+    }
+    if let Some(g) = value.strip_prefix("hg:") {
+        let g = parse_value(arg, Some(g.to_string()))?;
+        return Ok(Some(PhaseFunction::HenyeyGreenstein { g }));
+    }
+    if let Some(parameters) = value.strip_prefix("double-hg:") {
+        let items = parse_list(arg, Some(parameters.to_string()), 3)?;
+        return Ok(Some(PhaseFunction::DoubleHenyeyGreenstein {
+            g_forward: items[0],
+            g_backward: items[1],
+            weight: items[2],
+        }));
+    }
+    Err(format!("unknown phase function '{}'", value))
+}
+
+fn parse_alpha_mode(arg: &str, value: Option<String>) -> Result<AlphaMode, String> {
+    let value = next_value(arg, value)?;
+    if value == "stochastic" {
+        return Ok(AlphaMode::Stochastic);
+    }
+    if let Some(threshold) = value.strip_prefix("threshold:") {
+        let threshold = parse_value(arg, Some(threshold.to_string()))?;
+        return Ok(AlphaMode::Threshold(threshold));
+    }
+    Err(format!("unknown alpha mode '{}'", value))
+}
+
+fn parse_color(arg: &str, value: Option<String>) -> Result<Color, String> {
+    let items = parse_list(arg, value, 3)?;
+    Ok(Color::new(items[0], items[1], items[2]))
+}
+
+fn components(color: &Color) -> (f64, f64, f64) {
+    (color.x, color.y, color.z)
+}
+
+fn parse_refractive_index(arg: &str, value: Option<String>) -> Result<RefractiveIndex, String> {
+    let value = next_value(arg, value)?;
+    if let Some(index) = RefractiveIndex::from_name(&value) {
+        return Ok(index);
+    }
+    if let Some(coefficients) = value.strip_prefix("cauchy:") {
+        let items = parse_list(arg, Some(coefficients.to_string()), 2)?;
+        return Ok(RefractiveIndex::Cauchy {
+            a: items[0],
+            b: items[1],
+        });
+    }
+    if let Some(coefficients) = value.strip_prefix("sellmeier:") {
+        let items = parse_list(arg, Some(coefficients.to_string()), 6)?;
+        return Ok(RefractiveIndex::Sellmeier {
+            b: [items[0], items[1], items[2]],
+            c: [items[3], items[4], items[5]],
+        });
+    }
+    parse_value(arg, Some(value)).map(RefractiveIndex::Constant)
+}
+
+fn next_value(arg: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("missing value for {}", arg))
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: Option<String>) -> Result<T, String> {
+    let value = next_value(arg, value)?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, arg))
+}
+
+fn parse_list<T: std::str::FromStr>(
+    arg: &str,
+    value: Option<String>,
+    count: usize,
+) -> Result<Vec<T>, String> {
+    let value = next_value(arg, value)?;
+    let items = value
+        .split(',')
+        .map(|item| item.trim().parse())
+        .collect::<Result<Vec<T>, _>>()
+        .map_err(|_| format!("invalid value '{}' for {}", value, arg))?;
+    if items.len() != count {
+        return Err(format!("{} expects {} comma-separated values", arg, count));
+    }
+    Ok(items)
+}
@@ -0,0 +1,170 @@
+use super::constants::*;
+use super::vec3::*;
+
+// Angular distribution of the light scattered in a medium. The asymmetry `g` is the mean
+// cosine of the scattering angle: positive values scatter forward, negative backward.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PhaseFunction {
+    HenyeyGreenstein {
+        g: f64,
+    },
+    // Mix of a forward and a backward lobe, as seen in clouds; `weight` is the share of
+    // the first lobe.
+    DoubleHenyeyGreenstein {
+        g_forward: f64,
+        g_backward: f64,
+        weight: f64,
+    },
+}
+
+fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denominator * denominator.max(1e-12).sqrt())
+}
+
+// Cosine of the angle between the incoming and the scattered direction.
+fn sample_henyey_greenstein(g: f64, u: f64) -> f64 {
+    if g.abs() < 1e-3 {
+        return 1.0 - 2.0 * u;
+    }
+    let ratio = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+    ((1.0 + g * g - ratio * ratio) / (2.0 * g)).clamp(-1.0, 1.0)
+}
+
+impl PhaseFunction {
+    // Density per solid angle of scattering at `cos_theta` from the direction of travel.
+    // Sampling follows it exactly, so it is also the pdf of `sample`.
+    pub fn evaluate(&self, cos_theta: f64) -> f64 {
+        match *self {
+            PhaseFunction::HenyeyGreenstein { g } => henyey_greenstein(cos_theta, g),
+            PhaseFunction::DoubleHenyeyGreenstein {
+                g_forward,
+                g_backward,
+                weight,
+            } => {
+                weight * henyey_greenstein(cos_theta, g_forward)
+                    + (1.0 - weight) * henyey_greenstein(cos_theta, g_backward)
+            }
+        }
+    }
+
+    // Unit direction scattered from `direction` and its density, which light-sampled
+    // paths weigh against that of the light. `lobe_sample` picks the lobe of the double
+    // variant.
+    pub fn sample(&self, direction: &Vec3, u: (f64, f64), lobe_sample: f64) -> (Vec3, f64) {
+        let g = match *self {
+            PhaseFunction::HenyeyGreenstein { g } => g,
+            PhaseFunction::DoubleHenyeyGreenstein {
+                g_forward,
+                g_backward,
+                weight,
+            } => {
+                if lobe_sample < weight {
+                    g_forward
+                } else {
+                    g_backward
+                }
+            }
+        };
+        let cos_theta = sample_henyey_greenstein(g, u.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let w = direction.unit_vector();
+        let helper = if w.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let u_axis = w.cross(&helper).unit_vector();
+        let v_axis = w.cross(&u_axis);
+        (
+            sin_theta * phi.cos() * u_axis + sin_theta * phi.sin() * v_axis + cos_theta * w,
+            self.evaluate(cos_theta),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINS: usize = 20;
+    const SAMPLES: usize = 100000;
+
+    fn phase_functions() -> Vec<PhaseFunction> {
+        vec![
+            PhaseFunction::HenyeyGreenstein { g: 0.0 },
+            PhaseFunction::HenyeyGreenstein { g: 0.7 },
+            PhaseFunction::HenyeyGreenstein { g: -0.4 },
+            PhaseFunction::DoubleHenyeyGreenstein {
+                g_forward: 0.8,
+                g_backward: -0.3,
+                weight: 0.7,
+            },
+        ]
+    }
+
+    // Probability of scattering into each band of cos(theta), integrating the density
+    // over the sphere.
+    fn expected_histogram(phase: &PhaseFunction) -> Vec<f64> {
+        let width = 2.0 / BINS as f64;
+        let steps = 200;
+        (0..BINS)
+            .map(|bin| {
+                let start = -1.0 + bin as f64 * width;
+                (0..steps)
+                    .map(|i| {
+                        let cos_theta = start + (i as f64 + 0.5) * width / steps as f64;
+                        2.0 * PI * phase.evaluate(cos_theta) * width / steps as f64
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    // Fraction of sampled directions in each band, from stratified random numbers.
+    fn sampled_histogram(phase: &PhaseFunction) -> Vec<f64> {
+        let direction = Vec3::new(0.3, -0.5, 0.8).unit_vector();
+        let mut histogram = vec![0.0; BINS];
+        for k in 0..SAMPLES {
+            let u = (
+                (k as f64 + 0.5) / SAMPLES as f64,
+                (k as f64 * 0.618_033_988_75).fract(),
+            );
+            let lobe_sample = (k as f64 * 0.414_213_562_37).fract();
+            let (scattered, pdf) = phase.sample(&direction, u, lobe_sample);
+            assert!((scattered.length() - 1.0).abs() < 1e-9);
+            let cos_theta = scattered.dot(&direction);
+            assert!((pdf - phase.evaluate(cos_theta)).abs() <= 1e-6 * pdf);
+            let bin = (((cos_theta + 1.0) / 2.0 * BINS as f64) as usize).min(BINS - 1);
+            histogram[bin] += 1.0 / SAMPLES as f64;
+        }
+        histogram
+    }
+
+    #[test]
+    fn density_integrates_to_one() {
+        for phase in phase_functions() {
+            let total: f64 = expected_histogram(&phase).iter().sum();
+            assert!((total - 1.0).abs() < 1e-3, "{:?}: {}", phase, total);
+        }
+    }
+
+    #[test]
+    fn sampling_follows_the_density() {
+        for phase in phase_functions() {
+            let expected = expected_histogram(&phase);
+            let sampled = sampled_histogram(&phase);
+            for (bin, (e, s)) in expected.iter().zip(sampled.iter()).enumerate() {
+                assert!(
+                    (e - s).abs() < 0.002 + 0.01 * e,
+                    "{:?} bin {}: expected {}, sampled {}",
+                    phase,
+                    bin,
+                    e,
+                    s
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ProgressMode {
+    None,
+    Human,
+    Machine,
+}
+
+// Work is measured in pixel samples. Counters are atomic so that any number of rendering
+// threads can report into the same `Progress`; whichever thread notices that the report
+// interval has passed prints the line.
+pub struct Progress {
+    mode: ProgressMode,
+    total_samples: AtomicU64,
+    done_samples: AtomicU64,
+    // Tiles finished in the current pass over the image.
+    done_tiles: AtomicU64,
+    passes: AtomicU64,
+    start: Mutex<Instant>,
+    last_report: Mutex<Instant>,
+}
+
+impl ProgressMode {
+    pub fn from_name(name: &str) -> Option<ProgressMode> {
+        match name {
+            "none" => Some(ProgressMode::None),
+            "human" => Some(ProgressMode::Human),
+            "machine" => Some(ProgressMode::Machine),
+            _ => None,
+        }
+    }
+}
+
+impl Progress {
+    pub fn new(mode: ProgressMode) -> Progress {
+        Progress {
+            mode,
+            total_samples: AtomicU64::new(0),
+            done_samples: AtomicU64::new(0),
+            done_tiles: AtomicU64::new(0),
+            passes: AtomicU64::new(0),
+            start: Mutex::new(Instant::now()),
+            last_report: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Starts the clock for `total_samples` pixel samples of work. A total of zero means
+    // the amount of work is unknown and no percentage or ETA is shown.
+    pub fn begin(&self, total_samples: u64) {
+        self.total_samples.store(total_samples, Ordering::SeqCst);
+        self.done_samples.store(0, Ordering::SeqCst);
+        self.done_tiles.store(0, Ordering::SeqCst);
+        self.passes.store(0, Ordering::SeqCst);
+        *self.start.lock().unwrap() = Instant::now();
+        *self.last_report.lock().unwrap() = Instant::now();
+    }
+
+    pub fn add_samples(&self, samples: u64) {
+        self.done_samples.fetch_add(samples, Ordering::Relaxed);
+        self.report_if_due();
+    }
+
+    // Progressive renders visit every tile once per pass.
+    pub fn begin_pass(&self) {
+        self.passes.fetch_add(1, Ordering::SeqCst);
+        self.done_tiles.store(0, Ordering::SeqCst);
+    }
+
+    pub fn add_tile(&self) {
+        self.done_tiles.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Prints the final state regardless of the report interval.
+    pub fn finish(&self) {
+        self.report();
+    }
+
+    fn report_if_due(&self) {
+        if self.mode == ProgressMode::None {
+            return;
+        }
+        // Another thread holding the lock is already reporting.
+        if let Ok(mut last_report) = self.last_report.try_lock() {
+            if last_report.elapsed() >= REPORT_INTERVAL {
+                *last_report = Instant::now();
+                self.report();
+            }
+        }
+    }
+
+    fn report(&self) {
+        let elapsed = self.start.lock().unwrap().elapsed().as_secs_f64();
+        let total = self.total_samples.load(Ordering::Relaxed);
+        let done = self.done_samples.load(Ordering::Relaxed);
+        let tiles = self.done_tiles.load(Ordering::Relaxed);
+        let passes = self.passes.load(Ordering::Relaxed).max(1);
+        let samples_per_second = if elapsed > 0.0 {
+            done as f64 / elapsed
+        } else {
+            0.0
+        };
+        let fraction = if total > 0 {
+            Some((done as f64 / total as f64).min(1.0))
+        } else {
+            None
+        };
+        let eta = match fraction {
+            Some(fraction) if fraction > 0.0 => Some(elapsed * (1.0 - fraction) / fraction),
+            _ => None,
+        };
+        match self.mode {
+            ProgressMode::None => {}
+            ProgressMode::Human => eprintln!(
+                "{:>6}  elapsed {}  eta {}  {:.1}k samples/s  pass {}  {} tiles",
+                fraction.map_or("--".to_string(), |f| format!("{:.1}%", f * 100.0)),
+                format_duration(elapsed),
+                eta.map_or("--:--:--".to_string(), format_duration),
+                samples_per_second / 1000.0,
+                passes,
+                tiles
+            ),
+            // One line of key=value pairs per report; unknown values are written as -1.
+            ProgressMode::Machine => eprintln!(
+                "PROGRESS fraction={:.4} elapsed={:.2} eta={:.2} samples={} samples_per_second={:.0} pass={} tiles={}",
+                fraction.unwrap_or(-1.0),
+                elapsed,
+                eta.unwrap_or(-1.0),
+                done,
+                samples_per_second,
+                passes,
+                tiles
+            ),
+        }
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
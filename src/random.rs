@@ -2,7 +2,17 @@ extern crate rand;
 
 use super::constants::*;
 use super::vec3::*;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::seed_from_u64(0));
+}
+
+pub fn seed_random(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
 
 pub fn random_in_unit_sphere() -> Vec3 {
     loop {
@@ -26,8 +36,7 @@ pub fn random_in_hemisphere(normal: Vec3) -> Vec3 {
 }
 
 pub fn random_double() -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen::<f64>()
+    RNG.with(|rng| rng.borrow_mut().gen::<f64>())
 }
 
 pub fn random_double_in_limit(min: f64, max: f64) -> f64 {
@@ -40,15 +49,6 @@ pub fn random_integer_in_limit(min: usize, max: usize) -> usize {
     (f_min + (f_max - f_min) * random_double()) as usize
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p = 2.0 * Vec3::new(random_double(), random_double(), 0.0) - Vec3::new(1.0, 1.0, 0.0);
-        if p.dot(&p) < 1.0 {
-            return p;
-        }
-    }
-}
-
 pub fn random_unit_vector() -> Vec3 {
     let a = random_double_in_limit(0.0, 2.0 * PI);
     let z = random_double_in_limit(-1.0, 1.0);
@@ -67,3 +67,28 @@ pub fn random_color_in_limit(min: f64, max: f64) -> Vec3 {
         random_double_in_limit(min, max),
     )
 }
+
+pub fn sample_unit_vector(u: (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn sample_in_unit_sphere(u: (f64, f64), radius_sample: f64) -> Vec3 {
+    radius_sample.cbrt() * sample_unit_vector(u)
+}
+
+pub fn sample_in_unit_disk(u: (f64, f64)) -> Vec3 {
+    let offset_x = 2.0 * u.0 - 1.0;
+    let offset_y = 2.0 * u.1 - 1.0;
+    if offset_x == 0.0 && offset_y == 0.0 {
+        return Vec3::default();
+    }
+    let (r, theta) = if offset_x.abs() > offset_y.abs() {
+        (offset_x, 0.25 * PI * (offset_y / offset_x))
+    } else {
+        (offset_y, 0.5 * PI - 0.25 * PI * (offset_x / offset_y))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
@@ -1,9 +1,28 @@
+use super::interior::*;
+use super::spectrum::*;
 use super::vec3::*;
 
+#[derive(Copy, Clone)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
     pub time: f64,
+    // Set on the paths of spectral renders. Materials that depend on the wavelength read
+    // it and may return narrowed wavelengths on the scattered ray.
+    pub wavelengths: Option<SampledWavelengths>,
+    // Dielectrics the ray is inside of. Materials that change it set it on the scattered
+    // ray; unset means unchanged.
+    pub interiors: Option<InteriorStack>,
+    // Set on camera rays when textures are prefiltered; scattered rays do without.
+    pub differentials: Option<RayDifferentials>,
+}
+
+// Directions of the rays through the next pixels along the x and y axes of the image,
+// from the origin of a camera ray.
+#[derive(Copy, Clone)]
+pub struct RayDifferentials {
+    pub dx_direction: Vec3,
+    pub dy_direction: Vec3,
 }
 
 impl Ray {
@@ -12,6 +31,9 @@ impl Ray {
             origin: origin,
             direction: direction,
             time: time,
+            wavelengths: None,
+            interiors: None,
+            differentials: None,
         }
     }
 
@@ -5,6 +5,7 @@ use super::hittable::*;
 use super::material::*;
 use super::normal::*;
 use super::ray::*;
+use super::stats::*;
 use super::uv::*;
 use super::vec3::*;
 
@@ -37,8 +38,9 @@ pub struct YzRect<'a> {
 
 impl<'a> Hittable for XyRect<'a> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::XyRectTests);
         let t = (self.k - ray.origin.z) / ray.direction.z;
-        if t < t_min || t > t_max {
+        if !t.is_finite() || t < t_min || t > t_max {
             None
         } else {
             let x = ray.origin.x + t * ray.direction.x;
@@ -46,7 +48,7 @@ impl<'a> Hittable for XyRect<'a> {
             if x < self.x0 || x > self.x1 || y < self.y0 || y > self.y1 {
                 None
             } else {
-                Some(HitRecord::new(t, self, ray))
+                Some(HitRecord::new(t, self, ray)).filter(|record| record.is_opaque(ray))
             }
         }
     }
@@ -61,8 +63,9 @@ impl<'a> Hittable for XyRect<'a> {
 
 impl<'a> Hittable for XzRect<'a> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::XzRectTests);
         let t = (self.k - ray.origin.y) / ray.direction.y;
-        if t < t_min || t > t_max {
+        if !t.is_finite() || t < t_min || t > t_max {
             None
         } else {
             let x = ray.origin.x + t * ray.direction.x;
@@ -70,7 +73,7 @@ impl<'a> Hittable for XzRect<'a> {
             if x < self.x0 || x > self.x1 || z < self.z0 || z > self.z1 {
                 None
             } else {
-                Some(HitRecord::new(t, self, ray))
+                Some(HitRecord::new(t, self, ray)).filter(|record| record.is_opaque(ray))
             }
         }
     }
@@ -85,8 +88,9 @@ impl<'a> Hittable for XzRect<'a> {
 
 impl<'a> Hittable for YzRect<'a> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::YzRectTests);
         let t = (self.k - ray.origin.x) / ray.direction.x;
-        if t < t_min || t > t_max {
+        if !t.is_finite() || t < t_min || t > t_max {
             None
         } else {
             let y = ray.origin.y + t * ray.direction.y;
@@ -94,7 +98,7 @@ impl<'a> Hittable for YzRect<'a> {
             if y < self.y0 || y > self.y1 || z < self.z0 || z > self.z1 {
                 None
             } else {
-                Some(HitRecord::new(t, self, ray))
+                Some(HitRecord::new(t, self, ray)).filter(|record| record.is_opaque(ray))
             }
         }
     }
@@ -126,28 +130,28 @@ impl<'a> NormalOp for YzRect<'a> {
 }
 
 impl<'a> UvOp for XyRect<'a> {
-    fn get_u(&self, p: Vec3) -> f64 {
+    fn get_u(&self, p: Vec3, _outward_normal: Vec3) -> f64 {
         (p.x - self.x0) / (self.x1 - self.x0)
     }
-    fn get_v(&self, p: Vec3) -> f64 {
+    fn get_v(&self, p: Vec3, _outward_normal: Vec3) -> f64 {
         (p.y - self.y0) / (self.y1 - self.y0)
     }
 }
 
 impl<'a> UvOp for XzRect<'a> {
-    fn get_u(&self, p: Vec3) -> f64 {
+    fn get_u(&self, p: Vec3, _outward_normal: Vec3) -> f64 {
         (p.x - self.x0) / (self.x1 - self.x0)
     }
-    fn get_v(&self, p: Vec3) -> f64 {
+    fn get_v(&self, p: Vec3, _outward_normal: Vec3) -> f64 {
         (p.z - self.z0) / (self.z1 - self.z0)
     }
 }
 
 impl<'a> UvOp for YzRect<'a> {
-    fn get_u(&self, p: Vec3) -> f64 {
+    fn get_u(&self, p: Vec3, _outward_normal: Vec3) -> f64 {
         (p.z - self.z0) / (self.z1 - self.z0)
     }
-    fn get_v(&self, p: Vec3) -> f64 {
+    fn get_v(&self, p: Vec3, _outward_normal: Vec3) -> f64 {
         (p.y - self.y0) / (self.y1 - self.y0)
     }
 }
@@ -0,0 +1,458 @@
+use std::time::Instant;
+
+use super::aov::*;
+use super::camera::*;
+use super::color::*;
+use super::constants::*;
+use super::film::*;
+use super::hittable::*;
+use super::material::*;
+use super::progress::*;
+use super::random::*;
+use super::ray::*;
+use super::sampler::*;
+use super::spectrum::*;
+use super::stats::*;
+use super::texture::*;
+use super::tile::*;
+use super::vec3::*;
+
+pub struct AdaptiveSettings {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub threshold: f64,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RenderMode {
+    Shaded,
+    Normals,
+    FrontFace,
+    Depth,
+    Uv,
+    Albedo,
+    MaterialId,
+    ObjectId,
+    BvhHeatmap,
+}
+
+pub struct PassSchedule {
+    every_passes: Option<u32>,
+    every_seconds: Option<f64>,
+    passes: u32,
+    last: Instant,
+}
+
+pub struct Renderer<'a> {
+    pub camera: &'a Camera,
+    pub world: &'a dyn Hittable,
+    pub background: Color,
+    pub max_depth: i32,
+    pub seed: u64,
+    pub tiles: Vec<PixelBounds>,
+    pub progress: &'a Progress,
+    pub mode: RenderMode,
+    // Distance (depth mode) or BVH node visits (heatmap mode) shown at full intensity.
+    pub debug_max: f64,
+    // Traces sampled wavelengths instead of RGB when set.
+    pub spectral: Option<SpectralConversion>,
+    // Gives camera rays differentials, so that textures can be filtered over a pixel.
+    pub ray_differentials: bool,
+}
+
+// Product of the attenuations along a path, per RGB channel or, in spectral renders, per
+// sampled wavelength.
+enum Throughput<'a> {
+    Rgb(Color),
+    Spectral(SampledSpectrum, SampledWavelengths, &'a SpectralConversion),
+}
+
+impl RenderMode {
+    pub fn from_name(name: &str) -> Option<RenderMode> {
+        match name {
+            "shaded" => Some(RenderMode::Shaded),
+            "normals" => Some(RenderMode::Normals),
+            "front-face" => Some(RenderMode::FrontFace),
+            "depth" => Some(RenderMode::Depth),
+            "uv" => Some(RenderMode::Uv),
+            "albedo" => Some(RenderMode::Albedo),
+            "material-id" => Some(RenderMode::MaterialId),
+            "object-id" => Some(RenderMode::ObjectId),
+            "bvh-heatmap" => Some(RenderMode::BvhHeatmap),
+            _ => None,
+        }
+    }
+
+    // Ids are derived from addresses, which differ between processes.
+    pub fn is_process_local(&self) -> bool {
+        matches!(self, RenderMode::MaterialId | RenderMode::ObjectId)
+    }
+}
+
+impl<'a> Throughput<'a> {
+    // Working-space color of RGB light arriving over the path.
+    fn light(&self, light: Color) -> Color {
+        match self {
+            Throughput::Rgb(throughput) => *throughput * light,
+            Throughput::Spectral(throughput, wavelengths, conversion) => conversion.radiance_color(
+                &(*throughput * conversion.upsample(light, wavelengths)),
+                wavelengths,
+            ),
+        }
+    }
+
+    fn emitted(&self, record: &HitRecord) -> Color {
+        match self {
+            Throughput::Rgb(throughput) => {
+                *throughput * record.material.emitted(record.u, record.v, &record.p)
+            }
+            Throughput::Spectral(throughput, wavelengths, conversion) => {
+                let emitted = record.material.emitted_spectrum(
+                    record.u,
+                    record.v,
+                    &record.p,
+                    wavelengths,
+                    conversion,
+                );
+                conversion.radiance_color(&(*throughput * emitted), wavelengths)
+            }
+        }
+    }
+
+    // White leaves the throughput untouched; its upsampled spectrum is only close to flat.
+    fn scale(&mut self, factor: Color) {
+        if factor.x == 1.0 && factor.y == 1.0 && factor.z == 1.0 {
+            return;
+        }
+        match self {
+            Throughput::Rgb(throughput) => *throughput *= factor,
+            Throughput::Spectral(throughput, wavelengths, conversion) => {
+                *throughput *= conversion.upsample(factor, wavelengths)
+            }
+        }
+    }
+
+    fn attenuate(&mut self, attenuation: Color, scattered: &Ray) {
+        match self {
+            Throughput::Rgb(throughput) => *throughput *= attenuation,
+            Throughput::Spectral(throughput, wavelengths, conversion) => {
+                if let Some(narrowed) = scattered.wavelengths {
+                    *wavelengths = narrowed;
+                }
+                *throughput *= conversion.upsample(attenuation, wavelengths);
+            }
+        }
+    }
+}
+
+impl<'a> Renderer<'a> {
+    // Follows the path iteratively, keeping the product of the attenuations seen so far.
+    pub fn trace_path(&self, ray: &Ray, sampler: &mut dyn Sampler) -> PathSample {
+        let mut sample = PathSample::default();
+        let mut ray = *ray;
+        let mut throughput = match (&self.spectral, ray.wavelengths) {
+            (Some(conversion), Some(wavelengths)) => {
+                Throughput::Spectral(SampledSpectrum::constant(1.0), wavelengths, conversion)
+            }
+            _ => Throughput::Rgb(Color::new(1.0, 1.0, 1.0)),
+        };
+        for bounces in 0..self.max_depth {
+            let mut record = match self.world.hit(&ray, 0.0001, INFINITY) {
+                Some(record) => record,
+                None => {
+                    sample.add_light(bounces, throughput.light(self.background));
+                    break;
+                }
+            };
+            count(Counter::PathVertices);
+            if let Some(differentials) = &ray.differentials {
+                record.footprint = self.footprint(&ray, &record, differentials);
+            }
+            if let Some(interiors) = &ray.interiors {
+                throughput.scale(interiors.transmittance(record.t * ray.direction.length()));
+            }
+            throughput.scale(record.weight);
+            if bounces == 0 {
+                sample.first_hit = Some(FirstHit {
+                    albedo: record.material.albedo(record.u, record.v, &record.p),
+                    normal: record.normal,
+                    depth: record.t * ray.direction.length(),
+                    object_id: record.object_id,
+                });
+            }
+            sample.add_light(bounces, throughput.emitted(&record));
+            let mut scattered: Ray = Default::default();
+            let mut attenuation = Default::default();
+            if !record
+                .material
+                .scatter(&ray, &record, &mut attenuation, &mut scattered, sampler)
+            {
+                break;
+            }
+            if bounces + 1 < self.max_depth {
+                count(Counter::SecondaryRays);
+            }
+            throughput.attenuate(attenuation, &scattered);
+            if scattered.wavelengths.is_none() {
+                scattered.wavelengths = ray.wavelengths;
+            }
+            if scattered.interiors.is_none() {
+                scattered.interiors = ray.interiors;
+            }
+            ray = scattered;
+        }
+        sample
+    }
+
+    // Change of the texture coordinates towards the neighbouring pixels, from where their
+    // rays hit the same object. Unknown at silhouettes, where they hit something else.
+    fn footprint(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        differentials: &RayDifferentials,
+    ) -> Option<UvFootprint> {
+        let neighbour = |direction: Vec3| {
+            count(Counter::DifferentialRays);
+            let hit =
+                self.world
+                    .hit(&Ray::new(ray.origin, direction, ray.time), 0.0001, INFINITY)?;
+            if hit.object_id != record.object_id {
+                return None;
+            }
+            // Coordinates that wrap around, as at the seam of a sphere, take the short way.
+            let wrapped = |delta: f64| delta - delta.round();
+            Some((wrapped(hit.u - record.u), wrapped(hit.v - record.v)))
+        };
+        let (du_dx, dv_dx) = neighbour(differentials.dx_direction)?;
+        let (du_dy, dv_dy) = neighbour(differentials.dy_direction)?;
+        Some(UvFootprint {
+            du_dx,
+            dv_dx,
+            du_dy,
+            dv_dy,
+        })
+    }
+
+    // Primary-hit visualizations; nothing is scattered and misses are black.
+    pub fn debug_color(&self, ray: &Ray) -> Color {
+        let visits_before = counter_value(Counter::BvhNodeVisits);
+        let hit = self.world.hit(ray, 0.0001, INFINITY);
+        let record = match (self.mode, hit) {
+            (RenderMode::BvhHeatmap, _) => {
+                let visits = counter_value(Counter::BvhNodeVisits) - visits_before;
+                return heatmap(visits as f64 / self.debug_max);
+            }
+            (_, Some(record)) => record,
+            (_, None) => return Default::default(),
+        };
+        match self.mode {
+            RenderMode::Normals => 0.5 * (record.normal + Vec3::new(1.0, 1.0, 1.0)),
+            RenderMode::FrontFace if record.front_face => Color::new(0.0, 1.0, 0.0),
+            RenderMode::FrontFace => Color::new(1.0, 0.0, 0.0),
+            RenderMode::Depth => {
+                let distance = record.t * ray.direction.length() / self.debug_max;
+                let gray = distance.min(1.0);
+                Color::new(gray, gray, gray)
+            }
+            RenderMode::Uv => Color::new(record.u, record.v, 0.0),
+            RenderMode::Albedo => record.material.albedo(record.u, record.v, &record.p),
+            RenderMode::MaterialId => {
+                id_color(record.material as *const dyn Material as *const u8 as usize)
+            }
+            RenderMode::ObjectId => id_color(record.object_id),
+            RenderMode::Shaded | RenderMode::BvhHeatmap => unreachable!(),
+        }
+    }
+
+    pub fn render_pixel(
+        &self,
+        film: &mut Film,
+        sampler: &mut dyn Sampler,
+        x: u32,
+        y: u32,
+        first_sample: u32,
+        sample_count: u32,
+    ) {
+        for s in first_sample..first_sample + sample_count {
+            sampler.start_pixel_sample(x, y, s);
+            seed_random(hash(&[self.seed, x as u64, y as u64, s as u64]));
+            let (du, dv) = sampler.get_pixel_2d();
+            let film_x = x as f64 + du;
+            let film_y = y as f64 + dv;
+            let mut ray = self.camera.get_ray(
+                film_x / film.width as f64,
+                film_y / film.height as f64,
+                sampler,
+            );
+            if self.ray_differentials {
+                ray.differentials = Some(self.camera.differentials(
+                    &ray,
+                    1.0 / film.width as f64,
+                    1.0 / film.height as f64,
+                ));
+            }
+            if self.spectral.is_some() && self.mode == RenderMode::Shaded {
+                ray.wavelengths = Some(SampledWavelengths::sample(sampler.get_1d()));
+            }
+            count(Counter::CameraRays);
+            let sample = match self.mode {
+                RenderMode::Shaded => self.trace_path(&ray, sampler),
+                _ => PathSample::from_color(self.debug_color(&ray)),
+            };
+            film.add_sample(film_x, film_y, &sample);
+        }
+        self.progress.add_samples(sample_count as u64);
+    }
+
+    fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.tiles.iter().flat_map(|tile| tile.pixels())
+    }
+
+    pub fn min_sample_count(&self, film: &Film) -> u32 {
+        self.pixels()
+            .map(|(x, y)| film.pixel(x, y).sample_count)
+            .min()
+            .unwrap_or(0)
+    }
+
+    // Pixel samples still missing for every pixel to reach `samples`.
+    fn remaining_samples(&self, film: &Film, samples: u32) -> u64 {
+        self.pixels()
+            .map(|(x, y)| samples.saturating_sub(film.pixel(x, y).sample_count) as u64)
+            .sum()
+    }
+
+    // Brings every pixel up to `samples`, continuing from the samples already on the film.
+    pub fn render(&self, film: &mut Film, sampler: &mut dyn Sampler, samples: u32) {
+        for tile in &self.tiles {
+            self.render_tile(film, sampler, tile, samples);
+        }
+    }
+
+    pub fn render_tile(
+        &self,
+        film: &mut Film,
+        sampler: &mut dyn Sampler,
+        tile: &PixelBounds,
+        samples: u32,
+    ) {
+        for (x, y) in tile.pixels() {
+            let sample_count = film.pixel(x, y).sample_count;
+            if sample_count < samples {
+                self.render_pixel(film, sampler, x, y, sample_count, samples - sample_count);
+            }
+        }
+        self.progress.add_tile();
+    }
+
+    // Renders the whole image `pass_samples` at a time until every pixel has `samples`.
+    // `after_pass` is called after each pass and stops the render by returning false.
+    pub fn render_progressive(
+        &self,
+        film: &mut Film,
+        sampler: &mut dyn Sampler,
+        samples: u32,
+        pass_samples: u32,
+        after_pass: &mut dyn FnMut(&Film) -> bool,
+    ) {
+        if self.pixels().next().is_none() {
+            return;
+        }
+        self.progress.begin(self.remaining_samples(film, samples));
+        while self.min_sample_count(film) < samples {
+            let target = (self.min_sample_count(film) + pass_samples).min(samples);
+            self.progress.begin_pass();
+            self.render(film, sampler, target);
+            if !after_pass(film) {
+                break;
+            }
+        }
+    }
+
+    // Every pixel first gets `min_samples`; then pixels whose estimated relative error is
+    // still above the threshold receive further batches until they converge or reach
+    // `max_samples`. Progress is measured against `max_samples` everywhere, so the ETA is
+    // an upper bound.
+    pub fn render_adaptive(
+        &self,
+        film: &mut Film,
+        sampler: &mut dyn Sampler,
+        settings: &AdaptiveSettings,
+        after_pass: &mut dyn FnMut(&Film) -> bool,
+    ) {
+        self.progress
+            .begin(self.remaining_samples(film, settings.max_samples));
+        self.render(film, sampler, settings.min_samples);
+        if !after_pass(film) {
+            return;
+        }
+        let batch = settings.min_samples.max(1);
+        loop {
+            let mut active_pixels = 0;
+            for (x, y) in self.pixels() {
+                let sample_count = film.pixel(x, y).sample_count;
+                if sample_count < settings.max_samples
+                    && film.pixel_error(x, y) > settings.threshold
+                {
+                    let count = batch.min(settings.max_samples - sample_count);
+                    self.render_pixel(film, sampler, x, y, sample_count, count);
+                    active_pixels += 1;
+                }
+            }
+            if active_pixels == 0 || !after_pass(film) {
+                break;
+            }
+        }
+    }
+}
+
+fn id_color(id: usize) -> Color {
+    let bits = hash(&[id as u64]);
+    Color::new(
+        (bits & 0xff) as f64 / 255.0,
+        (bits >> 8 & 0xff) as f64 / 255.0,
+        (bits >> 16 & 0xff) as f64 / 255.0,
+    )
+}
+
+// Blue through cyan, green and yellow to red; values above 1 stay red.
+fn heatmap(value: f64) -> Color {
+    let stops = [
+        Color::new(0.0, 0.0, 1.0),
+        Color::new(0.0, 1.0, 1.0),
+        Color::new(0.0, 1.0, 0.0),
+        Color::new(1.0, 1.0, 0.0),
+        Color::new(1.0, 0.0, 0.0),
+    ];
+    let position = value.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+    let index = (position as usize).min(stops.len() - 2);
+    let fraction = position - index as f64;
+    (1.0 - fraction) * stops[index] + fraction * stops[index + 1]
+}
+
+impl PassSchedule {
+    pub fn new(every_passes: Option<u32>, every_seconds: Option<f64>) -> PassSchedule {
+        PassSchedule {
+            every_passes,
+            every_seconds,
+            passes: 0,
+            last: Instant::now(),
+        }
+    }
+
+    // Counts one pass and reports whether the schedule fires on it.
+    pub fn due(&mut self) -> bool {
+        self.passes += 1;
+        let passes_due = self
+            .every_passes
+            .is_some_and(|passes| self.passes.is_multiple_of(passes));
+        let seconds_due = self
+            .every_seconds
+            .is_some_and(|seconds| self.last.elapsed().as_secs_f64() >= seconds);
+        if passes_due || seconds_due {
+            self.last = Instant::now();
+        }
+        passes_due || seconds_due
+    }
+}
@@ -0,0 +1,514 @@
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON * 0.5;
+const HALTON_PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None,
+        }
+    }
+
+    pub fn make(&self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(samples_per_pixel, seed)),
+        }
+    }
+}
+
+// Every sampler is a pure function of (seed, pixel, sample index, dimension), so a pixel
+// sample can be regenerated on any machine or after a restart.
+pub trait Sampler {
+    fn samples_per_pixel(&self) -> u32;
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+    fn get_pixel_2d(&mut self) -> (f64, f64) {
+        self.get_2d()
+    }
+}
+
+pub struct IndependentSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    rng: Pcg32,
+}
+
+pub struct StratifiedSampler {
+    x_samples: u32,
+    y_samples: u32,
+    seed: u64,
+    pixel_hash: u64,
+    sample_index: u32,
+    dimension: u32,
+    rng: Pcg32,
+}
+
+pub struct HaltonSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel_hash: u64,
+    sample_index: u32,
+    dimension: u32,
+    rng: Pcg32,
+}
+
+pub struct SobolSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel_hash: u64,
+    sample_index: u32,
+    dimension: u32,
+    directions: [u32; 32],
+}
+
+impl IndependentSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            samples_per_pixel,
+            seed,
+            rng: Pcg32::new(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.rng = Pcg32::new(hash(&[self.seed, pixel_key(x, y), sample_index as u64]));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.uniform()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.uniform(), self.rng.uniform())
+    }
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> StratifiedSampler {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        let mut x_samples = (samples_per_pixel as f64).sqrt() as u32;
+        while !samples_per_pixel.is_multiple_of(x_samples) {
+            x_samples -= 1;
+        }
+        StratifiedSampler {
+            x_samples,
+            y_samples: samples_per_pixel / x_samples,
+            seed,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed),
+        }
+    }
+
+    fn stratum(&mut self, count: u32) -> Option<u32> {
+        let dimension_hash = hash(&[self.pixel_hash, self.dimension as u64, self.seed]);
+        self.dimension += 1;
+        if self.sample_index < count {
+            Some(permutation_element(
+                self.sample_index,
+                count,
+                dimension_hash as u32,
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.x_samples * self.y_samples
+    }
+
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel_hash = pixel_key(x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        self.rng = Pcg32::new(hash(&[self.seed, self.pixel_hash, sample_index as u64]));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let count = self.samples_per_pixel();
+        match self.stratum(count) {
+            Some(stratum) => (stratum as f64 + self.rng.uniform()) / count as f64,
+            None => self.rng.uniform(),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let count = self.samples_per_pixel();
+        match self.stratum(count) {
+            Some(stratum) => (
+                ((stratum % self.x_samples) as f64 + self.rng.uniform()) / self.x_samples as f64,
+                ((stratum / self.x_samples) as f64 + self.rng.uniform()) / self.y_samples as f64,
+            ),
+            None => (self.rng.uniform(), self.rng.uniform()),
+        }
+    }
+}
+
+impl HaltonSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            samples_per_pixel,
+            seed,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed),
+        }
+    }
+
+    fn sample_dimension(&mut self) -> f64 {
+        let dimension = self.dimension as usize;
+        self.dimension += 1;
+        if dimension < HALTON_PRIMES.len() {
+            owen_scrambled_radical_inverse(
+                HALTON_PRIMES[dimension],
+                self.sample_index as u64,
+                hash(&[self.seed, self.pixel_hash, dimension as u64]),
+            )
+        } else {
+            self.rng.uniform()
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel_hash = pixel_key(x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        self.rng = Pcg32::new(hash(&[self.seed, self.pixel_hash, sample_index as u64]));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.sample_dimension()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.sample_dimension(), self.sample_dimension())
+    }
+}
+
+// Owen-scrambled Sobol' points (0,2)-sequence padded to higher dimensions by shuffling
+// the index per dimension pair, as described by Burley, "Practical Hash-based Owen
+// Scrambling", JCGT 2020.
+impl SobolSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> SobolSampler {
+        let mut directions = [0u32; 32];
+        directions[0] = 1 << 31;
+        for i in 1..32 {
+            directions[i] = directions[i - 1] ^ (directions[i - 1] >> 1);
+        }
+        SobolSampler {
+            samples_per_pixel,
+            seed,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+            directions,
+        }
+    }
+
+    fn sobol(&self, index: u32, dimension: usize) -> u32 {
+        let mut x = 0;
+        for bit in 0..32 {
+            if (index >> bit) & 1 != 0 {
+                x ^= match dimension {
+                    0 => 1 << (31 - bit),
+                    _ => self.directions[bit],
+                };
+            }
+        }
+        x
+    }
+
+    fn next_hash(&mut self) -> u64 {
+        let dimension_hash = hash(&[self.seed, self.pixel_hash, self.dimension as u64]);
+        self.dimension += 1;
+        dimension_hash
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel_hash = pixel_key(x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension_hash = self.next_hash();
+        let index = nested_uniform_scramble(self.sample_index, dimension_hash as u32);
+        let x = self.sobol(index, 0);
+        to_unit_float(nested_uniform_scramble(x, (dimension_hash >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension_hash = self.next_hash();
+        let index = nested_uniform_scramble(self.sample_index, dimension_hash as u32);
+        let seed_x = mix_bits(dimension_hash ^ 0x9e37_79b9_7f4a_7c15);
+        let seed_y = mix_bits(seed_x);
+        (
+            to_unit_float(nested_uniform_scramble(self.sobol(index, 0), seed_x as u32)),
+            to_unit_float(nested_uniform_scramble(self.sobol(index, 1), seed_y as u32)),
+        )
+    }
+}
+
+struct Pcg32 {
+    state: u64,
+}
+
+impl Pcg32 {
+    fn new(seed: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state
+            .wrapping_mul(0x5851_f42d_4c95_7f2d)
+            .wrapping_add(0x14057b7ef767814f);
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rot = (old_state >> 59) as u32;
+        xor_shifted.rotate_right(rot)
+    }
+
+    fn uniform(&mut self) -> f64 {
+        to_unit_float(self.next_u32())
+    }
+}
+
+fn to_unit_float(x: u32) -> f64 {
+    (x as f64 / 4294967296.0).min(ONE_MINUS_EPSILON)
+}
+
+fn pixel_key(x: u32, y: u32) -> u64 {
+    ((y as u64) << 32) | x as u64
+}
+
+pub fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+pub fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |h, v| mix_bits(h ^ mix_bits(*v)))
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Kensler, "Correlated Multi-Jittered Sampling": the i-th element of a random
+// permutation of [0, n) without storing the permutation.
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+fn owen_scrambled_radical_inverse(base: u64, mut a: u64, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed_digits: u64 = 0;
+    while 1.0 - (base - 1) as f64 * inv_base_m < 1.0 {
+        let next = a / base;
+        let digit_hash = mix_bits(seed ^ reversed_digits) as u32;
+        let digit = permutation_element((a - next * base) as u32, base as u32, digit_hash) as u64;
+        // Large bases run out of u64 digits before f64 precision.
+        reversed_digits = match reversed_digits.checked_mul(base) {
+            Some(shifted) if shifted <= u64::MAX - digit => shifted + digit,
+            _ => break,
+        };
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    (reversed_digits as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    fn first_1d(sampler: &mut dyn Sampler, x: u32, y: u32) -> Vec<f64> {
+        (0..sampler.samples_per_pixel())
+            .map(|index| {
+                sampler.start_pixel_sample(x, y, index);
+                sampler.get_1d()
+            })
+            .collect()
+    }
+
+    fn assert_one_per_stratum(values: &[f64]) {
+        let mut strata = vec![0; values.len()];
+        for value in values {
+            strata[(value * values.len() as f64) as usize] += 1;
+        }
+        assert!(strata.iter().all(|&count| count == 1), "{:?}", strata);
+    }
+
+    #[test]
+    fn stratified_fills_every_stratum_once() {
+        for &samples in &[16, 12, 7] {
+            let mut sampler = StratifiedSampler::new(samples, 3);
+            for &(x, y) in &[(0, 0), (17, 5)] {
+                assert_one_per_stratum(&first_1d(&mut sampler, x, y));
+
+                let mut cells = vec![0; samples as usize];
+                for index in 0..samples {
+                    sampler.start_pixel_sample(x, y, index);
+                    sampler.get_1d();
+                    let (u, v) = sampler.get_2d();
+                    let column = (u * sampler.x_samples as f64) as u32;
+                    let row = (v * sampler.y_samples as f64) as u32;
+                    cells[(row * sampler.x_samples + column) as usize] += 1;
+                }
+                assert!(cells.iter().all(|&count| count == 1), "{:?}", cells);
+            }
+        }
+    }
+
+    #[test]
+    fn low_discrepancy_prefixes_fill_every_stratum_once() {
+        for k in 0..8 {
+            let samples = 1 << k;
+            let mut samplers: Vec<Box<dyn Sampler>> = vec![
+                Box::new(HaltonSampler::new(samples, 11)),
+                Box::new(SobolSampler::new(samples, 11)),
+            ];
+            for sampler in samplers.iter_mut() {
+                assert_one_per_stratum(&first_1d(sampler.as_mut(), 42, 7));
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_reproducible() {
+        for kind in KINDS.iter() {
+            let mut first = kind.make(16, 5);
+            let mut second = kind.make(16, 5);
+            // Visit the pixels in a different order to rule out hidden state.
+            second.start_pixel_sample(3, 9, 2);
+            second.get_2d();
+            for &(x, y, index) in &[(0, 0, 0), (3, 9, 7), (640, 360, 15)] {
+                first.start_pixel_sample(x, y, index);
+                second.start_pixel_sample(x, y, index);
+                for _ in 0..40 {
+                    assert_eq!(first.get_1d().to_bits(), second.get_1d().to_bits());
+                    let (a, b) = (first.get_2d(), second.get_2d());
+                    assert_eq!(
+                        (a.0.to_bits(), a.1.to_bits()),
+                        (b.0.to_bits(), b.1.to_bits())
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_lie_in_the_unit_interval() {
+        for kind in KINDS.iter() {
+            let mut sampler = kind.make(64, 1);
+            for index in 0..64 {
+                sampler.start_pixel_sample(index * 13, index * 7, index);
+                for _ in 0..100 {
+                    let (u, v) = sampler.get_2d();
+                    for value in [sampler.get_1d(), u, v].iter() {
+                        assert!((0.0..1.0).contains(value), "{:?} gave {}", kind, value);
+                    }
+                }
+            }
+        }
+    }
+}
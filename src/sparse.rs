@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Result};
+use std::rc::Rc;
+
+use super::binio::*;
+use super::cube::*;
+use super::hittable::*;
+use super::material::*;
+use super::transform::*;
+use super::vec3::*;
+use super::volume::*;
+
+// Sparse voxel grids, as written by simulation tools, in a simple format modelled on
+// NanoVDB's leaf nodes. All values are little endian:
+//
+//   magic        8 bytes, "SVOLGRID"
+//   version      u32, 1
+//   background   f32, the value of voxels that are not active
+//   transform    12 f64: the rows of the 3x3 matrix from index to world space, then the
+//                world position of voxel (0, 0, 0)
+//   leaf count   u32
+//   leaves       for each leaf:
+//     origin     3 i32, index of its first voxel; multiples of 8
+//     mask       64 bytes, bit i % 8 of byte i / 8 set for each active voxel i
+//     values     512 f32, x varying fastest, then y, then z
+//
+// Voxel values sit at integer index coordinates and are interpolated trilinearly.
+// resources/smoke.svol is a small example, a lumpy ball where the smoke ball sits.
+const MAGIC: &[u8; 8] = b"SVOLGRID";
+const VERSION: u32 = 1;
+const LEAF_DIM: i32 = 8;
+const LEAF_VOXELS: usize = 512;
+
+pub struct SparseGrid {
+    leaves: HashMap<[i32; 3], Box<[f32; LEAF_VOXELS]>>,
+    background: f64,
+    index_to_world: Transform,
+    world_to_index: Transform,
+    // Bounds of the active voxels, in index space.
+    active_min: [i32; 3],
+    active_max: [i32; 3],
+    max_value: f64,
+}
+
+impl SparseGrid {
+    pub fn read(filename: &str) -> Result<SparseGrid> {
+        SparseGrid::decode(&mut BufReader::new(File::open(filename)?), filename)
+    }
+
+    fn decode(reader: &mut dyn Read, filename: &str) -> Result<SparseGrid> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data(format!(
+                "{} is not a sparse volume file",
+                filename
+            )));
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported sparse volume version {}",
+                version
+            )));
+        }
+        let background = read_f32(reader)?;
+        if !(background >= 0.0 && background.is_finite()) {
+            return Err(invalid_data(format!(
+                "invalid background density {}",
+                background
+            )));
+        }
+        let background = background as f64;
+        let mut index_to_world = [[0.0; 3]; 3];
+        for value in index_to_world.iter_mut().flatten() {
+            *value = read_f64(reader)?;
+        }
+        let translation = Vec3::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?);
+        let index_to_world = Transform::new(index_to_world, translation).ok_or_else(|| {
+            invalid_data("the index to world transform is not invertible".to_string())
+        })?;
+        // The count is not trusted for allocation; a truncated file fails reading leaves.
+        let leaf_count = read_u32(reader)?;
+        let mut grid = SparseGrid {
+            leaves: HashMap::new(),
+            background,
+            index_to_world,
+            world_to_index: index_to_world.inverse(),
+            active_min: [i32::MAX; 3],
+            active_max: [i32::MIN; 3],
+            max_value: background,
+        };
+        for _ in 0..leaf_count {
+            let origin = [read_i32(reader)?, read_i32(reader)?, read_i32(reader)?];
+            if origin.iter().any(|value| value % LEAF_DIM != 0) {
+                return Err(invalid_data(format!(
+                    "leaf origin {:?} is not a multiple of {}",
+                    origin, LEAF_DIM
+                )));
+            }
+            let mut mask = [0u8; LEAF_VOXELS / 8];
+            reader.read_exact(&mut mask)?;
+            let mut values = Box::new([0.0f32; LEAF_VOXELS]);
+            for (i, value) in values.iter_mut().enumerate() {
+                let stored = read_f32(reader)?;
+                if mask[i / 8] & (1 << (i % 8)) == 0 {
+                    *value = background as f32;
+                    continue;
+                }
+                if !(stored >= 0.0 && stored.is_finite()) {
+                    return Err(invalid_data(format!(
+                        "invalid density {} in leaf {:?}",
+                        stored, origin
+                    )));
+                }
+                *value = stored;
+                grid.max_value = grid.max_value.max(stored as f64);
+                let voxel = [
+                    i as i32 % LEAF_DIM,
+                    i as i32 / LEAF_DIM % LEAF_DIM,
+                    i as i32 / (LEAF_DIM * LEAF_DIM),
+                ];
+                for axis in 0..3 {
+                    grid.active_min[axis] = grid.active_min[axis].min(origin[axis] + voxel[axis]);
+                    grid.active_max[axis] = grid.active_max[axis].max(origin[axis] + voxel[axis]);
+                }
+            }
+            let key = origin.map(|value| value / LEAF_DIM);
+            if grid.leaves.insert(key, values).is_some() {
+                return Err(invalid_data(format!("duplicate leaf {:?}", origin)));
+            }
+        }
+        if grid.active_min[0] > grid.active_max[0] {
+            return Err(invalid_data(format!("{} has no active voxels", filename)));
+        }
+        Ok(grid)
+    }
+
+    // Box around the extent of the active voxels, in world space.
+    pub fn boundary<'a>(&self, material: &'a dyn Material) -> Rc<dyn Hittable + 'a> {
+        let corner = |index: [i32; 3], offset: f64| {
+            Vec3::new(
+                index[0] as f64 + offset,
+                index[1] as f64 + offset,
+                index[2] as f64 + offset,
+            )
+        };
+        Rc::new(AffineTransform::new(
+            Rc::new(Cube::new(
+                corner(self.active_min, -0.5),
+                corner(self.active_max, 0.5),
+                material,
+            )),
+            self.index_to_world,
+        ))
+    }
+
+    fn voxel(&self, index: [i32; 3]) -> f64 {
+        // Arithmetic shifts and masks round towards negative infinity.
+        let key = [index[0] >> 3, index[1] >> 3, index[2] >> 3];
+        match self.leaves.get(&key) {
+            Some(values) => {
+                let [x, y, z] = index.map(|value| (value & (LEAF_DIM - 1)) as usize);
+                values[x + 8 * (y + 8 * z)] as f64
+            }
+            None => self.background,
+        }
+    }
+}
+
+impl Density for SparseGrid {
+    fn density(&self, point: &Vec3) -> f64 {
+        let position = self.world_to_index.transform_point(*point);
+        let base = [position.x.floor(), position.y.floor(), position.z.floor()];
+        let fraction = [
+            position.x - base[0],
+            position.y - base[1],
+            position.z - base[2],
+        ];
+        let base = base.map(|value| value as i32);
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = base;
+            for axis in 0..3 {
+                if corner & (1 << axis) == 0 {
+                    weight *= 1.0 - fraction[axis];
+                } else {
+                    weight *= fraction[axis];
+                    index[axis] += 1;
+                }
+            }
+            value += weight * self.voxel(index);
+        }
+        value
+    }
+    fn max_density(&self) -> f64 {
+        self.max_value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    const IDENTITY: [[f64; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    struct Leaf {
+        origin: [i32; 3],
+        active: Vec<(usize, f32)>,
+    }
+
+    fn encode(
+        background: f32,
+        matrix: [[f64; 3]; 3],
+        translation: [f64; 3],
+        leaves: &[Leaf],
+    ) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&background.to_le_bytes());
+        for value in matrix.iter().flatten().chain(translation.iter()) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(leaves.len() as u32).to_le_bytes());
+        for leaf in leaves {
+            for value in &leaf.origin {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            let mut mask = [0u8; LEAF_VOXELS / 8];
+            let mut values = [0.0f32; LEAF_VOXELS];
+            for &(i, value) in &leaf.active {
+                mask[i / 8] |= 1 << (i % 8);
+                values[i] = value;
+            }
+            bytes.extend_from_slice(&mask);
+            for value in values.iter() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<SparseGrid> {
+        SparseGrid::decode(&mut &bytes[..], "test.svol")
+    }
+
+    fn error_kind(bytes: &[u8]) -> Option<ErrorKind> {
+        decode(bytes).err().map(|error| error.kind())
+    }
+
+    fn voxel_index(x: usize, y: usize, z: usize) -> usize {
+        x + 8 * (y + 8 * z)
+    }
+
+    fn single_leaf(origin: [i32; 3], active: Vec<(usize, f32)>) -> Vec<u8> {
+        encode(0.0, IDENTITY, [0.0; 3], &[Leaf { origin, active }])
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let bytes = encode(
+            0.25,
+            IDENTITY,
+            [0.0; 3],
+            &[
+                Leaf {
+                    origin: [0, 0, 0],
+                    active: vec![(voxel_index(1, 2, 3), 4.0), (voxel_index(7, 7, 7), 0.5)],
+                },
+                Leaf {
+                    origin: [-8, 16, 0],
+                    active: vec![(voxel_index(0, 0, 0), 2.0)],
+                },
+            ],
+        );
+        let grid = decode(&bytes).expect("valid grid");
+        assert_eq!(grid.voxel([1, 2, 3]), 4.0);
+        assert_eq!(grid.voxel([7, 7, 7]), 0.5);
+        assert_eq!(grid.voxel([-8, 16, 0]), 2.0);
+        // Inactive voxels, inside a leaf or not, read as the background.
+        assert_eq!(grid.voxel([0, 0, 0]), 0.25);
+        assert_eq!(grid.voxel([100, -100, 5]), 0.25);
+        assert_eq!(grid.max_density(), 4.0);
+        assert_eq!(grid.active_min, [-8, 2, 0]);
+        assert_eq!(grid.active_max, [7, 16, 7]);
+    }
+
+    #[test]
+    fn reads_the_sample_volume() {
+        let grid = SparseGrid::read("resources/smoke.svol").expect("sample volume");
+        assert!(grid.max_density() > 0.0);
+        assert!(grid.density(&Vec3::new(360.0, 150.0, 145.0)) > 0.0);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let valid = single_leaf([0, 0, 0], vec![(0, 1.0)]);
+        assert!(decode(&valid).is_ok());
+
+        let mut bad_magic = valid.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(error_kind(&bad_magic), Some(ErrorKind::InvalidData));
+
+        let mut bad_version = valid.clone();
+        bad_version[8..12].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(error_kind(&bad_version), Some(ErrorKind::InvalidData));
+
+        let mut bad_background = valid.clone();
+        bad_background[12..16].copy_from_slice(&(-1.0f32).to_le_bytes());
+        assert_eq!(error_kind(&bad_background), Some(ErrorKind::InvalidData));
+
+        let singular = encode(
+            0.0,
+            [[1.0, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+            [0.0; 3],
+            &[Leaf {
+                origin: [0, 0, 0],
+                active: vec![(0, 1.0)],
+            }],
+        );
+        assert_eq!(error_kind(&singular), Some(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let valid = single_leaf([0, 0, 0], vec![(0, 1.0)]);
+        for length in [0, 5, 8, 14, 60, 110, 130, valid.len() - 1].iter() {
+            assert_eq!(
+                error_kind(&valid[..*length]),
+                Some(ErrorKind::UnexpectedEof),
+                "length {}",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_leaves() {
+        for value in [-0.5, f32::NAN, f32::INFINITY].iter() {
+            let bytes = single_leaf([0, 0, 0], vec![(3, *value)]);
+            assert_eq!(error_kind(&bytes), Some(ErrorKind::InvalidData));
+        }
+        // Invalid values in inactive voxels are ignored.
+        let mut inactive = single_leaf([0, 0, 0], vec![(0, 1.0)]);
+        let last = inactive.len() - 4;
+        inactive[last..].copy_from_slice(&f32::NAN.to_le_bytes());
+        assert!(decode(&inactive).is_ok());
+
+        let misaligned = single_leaf([4, 0, 0], vec![(0, 1.0)]);
+        assert_eq!(error_kind(&misaligned), Some(ErrorKind::InvalidData));
+
+        let empty = single_leaf([0, 0, 0], vec![]);
+        assert_eq!(error_kind(&empty), Some(ErrorKind::InvalidData));
+
+        let leaf = || Leaf {
+            origin: [8, 0, 0],
+            active: vec![(0, 1.0)],
+        };
+        let duplicate = encode(0.0, IDENTITY, [0.0; 3], &[leaf(), leaf()]);
+        assert_eq!(error_kind(&duplicate), Some(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn interpolates_trilinearly_in_world_space() {
+        // Voxel (x, 0, 0) holds x inside a leaf straddling the origin, on a grid scaled
+        // by 2 and moved by (10, 0, 0).
+        let active = (0..8).map(|x| (voxel_index(x, 0, 0), x as f32)).collect();
+        let bytes = encode(
+            0.0,
+            [[2.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 2.0]],
+            [10.0, 0.0, 0.0],
+            &[Leaf {
+                origin: [0, 0, 0],
+                active,
+            }],
+        );
+        let grid = decode(&bytes).expect("valid grid");
+        let density = |x: f64, y: f64, z: f64| grid.density(&Vec3::new(x, y, z));
+        assert_eq!(density(10.0, 0.0, 0.0), 0.0);
+        assert_eq!(density(14.0, 0.0, 0.0), 2.0);
+        assert!((density(13.0, 0.0, 0.0) - 1.5).abs() < 1e-12);
+        // Halfway towards the inactive row at y = 1, and a quarter towards z = 1.
+        assert!((density(14.0, 1.0, 0.0) - 1.0).abs() < 1e-12);
+        assert!((density(14.0, 1.0, 0.5) - 0.75).abs() < 1e-12);
+        // Below the origin the lookup blends with the background of the leaf at -8.
+        assert!((density(15.0, -1.0, 0.0) - 1.25).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,254 @@
+use std::ops::Mul;
+use std::ops::MulAssign;
+
+use super::color::*;
+use super::colorspace::*;
+
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+// Wavelengths traced together by one path: the hero wavelength and evenly spaced
+// companions.
+pub const WAVELENGTH_SAMPLES: usize = 4;
+
+// Values of a spectrum at the wavelengths of a path.
+#[derive(Copy, Clone, Debug)]
+pub struct SampledSpectrum {
+    pub values: [f64; WAVELENGTH_SAMPLES],
+}
+
+// Wavelengths in nanometers with the density they were sampled with. A zero density
+// marks a wavelength that no longer contributes.
+#[derive(Copy, Clone, Debug)]
+pub struct SampledWavelengths {
+    pub lambda: [f64; WAVELENGTH_SAMPLES],
+    pub pdf: [f64; WAVELENGTH_SAMPLES],
+}
+
+// Converts spectral radiance to linear RGB of the working space and upsamples RGB
+// reflectances and emission to spectra. Spectra are related to RGB through the equal
+// energy illuminant, which is adapted to the working space's white so that a constant
+// spectrum comes out neutral.
+#[derive(Copy, Clone)]
+pub struct SpectralConversion {
+    xyz_to_rgb: Matrix3,
+    rgb_to_srgb: Matrix3,
+}
+
+impl SampledSpectrum {
+    pub fn constant(value: f64) -> SampledSpectrum {
+        SampledSpectrum {
+            values: [value; WAVELENGTH_SAMPLES],
+        }
+    }
+
+    pub fn from_fn(wavelengths: &SampledWavelengths, f: impl Fn(f64) -> f64) -> SampledSpectrum {
+        SampledSpectrum {
+            values: wavelengths.lambda.map(f),
+        }
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, rhs: Self) -> Self {
+        let mut values = self.values;
+        for (value, factor) in values.iter_mut().zip(rhs.values) {
+            *value *= factor;
+        }
+        SampledSpectrum { values }
+    }
+}
+
+impl MulAssign for SampledSpectrum {
+    fn mul_assign(&mut self, other: Self) {
+        *self = *self * other;
+    }
+}
+
+impl SampledWavelengths {
+    // Hero wavelength sampling: `u` places the hero wavelength and the others follow at
+    // equal offsets in sample space, all importance sampled towards the visible range.
+    pub fn sample(u: f64) -> SampledWavelengths {
+        let mut lambda = [0.0; WAVELENGTH_SAMPLES];
+        let mut pdf = [0.0; WAVELENGTH_SAMPLES];
+        for i in 0..WAVELENGTH_SAMPLES {
+            let offset = (u + i as f64 / WAVELENGTH_SAMPLES as f64).fract();
+            lambda[i] = sample_visible_wavelength(offset);
+            pdf[i] = visible_wavelength_pdf(lambda[i]);
+        }
+        SampledWavelengths { lambda, pdf }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    // Keeps only the hero wavelength, for scattering that depends on the wavelength such
+    // as dispersion. Its estimate then stands for all of them.
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1..].iter().all(|pdf| *pdf == 0.0) {
+            return;
+        }
+        for pdf in &mut self.pdf[1..] {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= WAVELENGTH_SAMPLES as f64;
+    }
+}
+
+// Density proportional to a fit of the visible sensitivity, from pbrt-v4.
+fn visible_wavelength_pdf(lambda: f64) -> f64 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.0;
+    }
+    0.0039398042 / (0.0072 * (lambda - 538.0)).cosh().powi(2)
+}
+
+fn sample_visible_wavelength(u: f64) -> f64 {
+    538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh()
+}
+
+// CIE 1931 color matching functions, as the multi-lobe fit of Wyman, Sloan and Shirley.
+pub fn cie_xyz(lambda: f64) -> Color {
+    let lobe = |mean: f64, sigma_below: f64, sigma_above: f64| {
+        let sigma = if lambda < mean {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (lambda - mean) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Color::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+// Planck's law for a black body at `temperature` kelvin, normalized to 1 at its peak.
+pub fn blackbody(lambda: f64, temperature: f64) -> f64 {
+    let planck = |lambda_nm: f64| {
+        let c = 299792458.0;
+        let h = 6.62606957e-34;
+        let kb = 1.3806488e-23;
+        let l = lambda_nm * 1e-9;
+        2.0 * h * c * c / (l.powi(5) * ((h * c / (l * kb * temperature)).exp() - 1.0))
+    };
+    let peak = 2.8977721e-3 / temperature * 1e9;
+    planck(lambda) / planck(peak)
+}
+
+// Smits' basis spectra for RGB to reflectance conversion, in ten bins from 380 to 720 nm.
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+// Smits' upsampling of a linear sRGB color: the smallest component as white, then the
+// secondary and primary colors that make up the rest. Wavelengths outside the table take
+// the value of the nearest bin.
+fn smits_spectrum(rgb: Color, lambda: f64) -> f64 {
+    let bin = (((lambda - 380.0) / 34.0).max(0.0) as usize).min(9);
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+    if r <= g && r <= b {
+        let rest = if g <= b {
+            (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        };
+        r * SMITS_WHITE[bin] + rest
+    } else if g <= r && g <= b {
+        let rest = if r <= b {
+            (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        };
+        g * SMITS_WHITE[bin] + rest
+    } else {
+        let rest = if r <= g {
+            (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        } else {
+            (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        };
+        b * SMITS_WHITE[bin] + rest
+    }
+}
+
+impl SpectralConversion {
+    pub fn new(working_space: ColorSpace) -> SpectralConversion {
+        let equal_energy = integrate_xyz(|_| 1.0);
+        let white = equal_energy / equal_energy.y;
+        let mut xyz_to_rgb = multiply(
+            &invert(&working_space.to_xyz()),
+            &adaptation_matrix(white, working_space.white()),
+        );
+        for value in xyz_to_rgb.iter_mut().flatten() {
+            *value /= equal_energy.y;
+        }
+        SpectralConversion {
+            xyz_to_rgb,
+            rgb_to_srgb: conversion_matrix(working_space, ColorSpace::Srgb),
+        }
+    }
+
+    // Colors outside the sRGB gamut are clipped to it first.
+    pub fn upsample(&self, color: Color, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        let rgb = transform_color(&self.rgb_to_srgb, color).map(|value| value.max(0.0));
+        SampledSpectrum::from_fn(wavelengths, |lambda| smits_spectrum(rgb, lambda))
+    }
+
+    // Monte Carlo estimate of the color of the spectrum from its sampled values.
+    pub fn radiance_color(
+        &self,
+        spectrum: &SampledSpectrum,
+        wavelengths: &SampledWavelengths,
+    ) -> Color {
+        let mut xyz = Color::default();
+        for i in 0..WAVELENGTH_SAMPLES {
+            if wavelengths.pdf[i] > 0.0 {
+                xyz += spectrum.values[i] / wavelengths.pdf[i] * cie_xyz(wavelengths.lambda[i]);
+            }
+        }
+        transform_color(&self.xyz_to_rgb, xyz / WAVELENGTH_SAMPLES as f64)
+    }
+
+    // Color of a spectrum given by its value at every wavelength, with its luminance
+    // relative to the constant spectrum of value 1.
+    pub fn spectrum_color(&self, spectrum: impl Fn(f64) -> f64) -> (Color, f64) {
+        let xyz = integrate_xyz(spectrum);
+        (
+            transform_color(&self.xyz_to_rgb, xyz),
+            xyz.y / integrate_xyz(|_| 1.0).y,
+        )
+    }
+}
+
+// Integral of the spectrum times the color matching functions, in 1 nm steps.
+fn integrate_xyz(spectrum: impl Fn(f64) -> f64) -> Color {
+    let mut xyz = Color::default();
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        xyz += spectrum(lambda) * cie_xyz(lambda);
+        lambda += 1.0;
+    }
+    xyz
+}
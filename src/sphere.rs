@@ -4,6 +4,7 @@ use super::hittable::*;
 use super::material::*;
 use super::normal::*;
 use super::ray::*;
+use super::stats::*;
 use super::uv::*;
 use super::vec3::*;
 
@@ -66,6 +67,7 @@ impl<'a> MovingSphere<'a> {
 
 impl<'a> Hittable for Sphere<'a> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::SphereTests);
         let oc = ray.origin - self.center;
         let a = ray.direction.squared_length();
         let half_b = oc.dot(&ray.direction);
@@ -73,13 +75,13 @@ impl<'a> Hittable for Sphere<'a> {
         let discriminant = half_b * half_b - a * c;
         if discriminant > 0.0 {
             let root = discriminant.sqrt();
-            let temp = (-half_b - root) / a;
-            if temp < t_max && temp > t_min {
-                return Some(HitRecord::new(temp, self, ray));
-            }
-            let temp = (-half_b + root) / a;
-            if temp < t_max && temp > t_min {
-                return Some(HitRecord::new(temp, self, ray));
+            for temp in [(-half_b - root) / a, (-half_b + root) / a] {
+                if temp < t_max && temp > t_min {
+                    let record = HitRecord::new(temp, self, ray);
+                    if record.is_opaque(ray) {
+                        return Some(record);
+                    }
+                }
             }
         }
         return None;
@@ -96,6 +98,7 @@ impl<'a> Hittable for Sphere<'a> {
 
 impl<'a> Hittable for MovingSphere<'a> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::MovingSphereTests);
         let oc = ray.origin - self.center(ray.time);
         let a = ray.direction.squared_length();
         let half_b = oc.dot(&ray.direction);
@@ -105,15 +108,20 @@ impl<'a> Hittable for MovingSphere<'a> {
             return None;
         }
         let sqrtd_discriminant = discriminant.sqrt();
-        // Find the nearest root that lies in the acceptable range.
-        let mut root = (-half_b - sqrtd_discriminant) / a;
-        if root < t_min || root > t_max {
-            root = (-half_b + sqrtd_discriminant) / a;
+        // Find the nearest root that lies in the acceptable range and is not a hole.
+        for root in [
+            (-half_b - sqrtd_discriminant) / a,
+            (-half_b + sqrtd_discriminant) / a,
+        ] {
             if root < t_min || root > t_max {
-                return None;
+                continue;
+            }
+            let record = HitRecord::new(root, self, ray);
+            if record.is_opaque(ray) {
+                return Some(record);
             }
         }
-        Some(HitRecord::new(root, self, ray))
+        None
     }
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
@@ -189,19 +197,19 @@ impl<'a> MaterialOp for &MovingSphere<'a> {
 }
 
 impl<'a> UvOp for Sphere<'a> {
-    fn get_u(&self, p: Vec3) -> f64 {
-        get_sphere_u(p)
+    fn get_u(&self, _point: Vec3, outward_normal: Vec3) -> f64 {
+        get_sphere_u(outward_normal)
     }
-    fn get_v(&self, p: Vec3) -> f64 {
-        get_sphere_v(p)
+    fn get_v(&self, _point: Vec3, outward_normal: Vec3) -> f64 {
+        get_sphere_v(outward_normal)
     }
 }
 
 impl<'a> UvOp for MovingSphere<'a> {
-    fn get_u(&self, p: Vec3) -> f64 {
-        get_sphere_u(p)
+    fn get_u(&self, _point: Vec3, outward_normal: Vec3) -> f64 {
+        get_sphere_u(outward_normal)
     }
-    fn get_v(&self, p: Vec3) -> f64 {
-        get_sphere_v(p)
+    fn get_v(&self, _point: Vec3, outward_normal: Vec3) -> f64 {
+        get_sphere_v(outward_normal)
     }
 }
@@ -0,0 +1,161 @@
+use std::cell::Cell;
+use std::fs::File;
+use std::io::Write;
+use std::time::Duration;
+
+#[derive(Copy, Clone)]
+pub enum Counter {
+    CameraRays,
+    SecondaryRays,
+    // Rays towards neighbouring pixels that measure texture footprints.
+    DifferentialRays,
+    PathVertices,
+    BvhNodeVisits,
+    SphereTests,
+    MovingSphereTests,
+    XyRectTests,
+    XzRectTests,
+    YzRectTests,
+    CubeTests,
+    ConstantMediumTests,
+    HeterogeneousMediumTests,
+    // Not a counter: the number of variants above.
+    Count,
+}
+
+const COUNTER_COUNT: usize = Counter::Count as usize;
+
+const INTERSECTION_TESTS: [(Counter, &str); 8] = [
+    (Counter::SphereTests, "sphere"),
+    (Counter::MovingSphereTests, "moving_sphere"),
+    (Counter::XyRectTests, "xy_rect"),
+    (Counter::XzRectTests, "xz_rect"),
+    (Counter::YzRectTests, "yz_rect"),
+    (Counter::CubeTests, "cube"),
+    (Counter::ConstantMediumTests, "constant_medium"),
+    (Counter::HeterogeneousMediumTests, "heterogeneous_medium"),
+];
+
+// Counters live in thread-local storage so that counting never synchronizes; a thread
+// collects its own totals with `take_counters`.
+thread_local! {
+    static COUNTERS: [Cell<u64>; COUNTER_COUNT] = Default::default();
+}
+
+pub fn count(counter: Counter) {
+    COUNTERS.with(|counters| {
+        let cell = &counters[counter as usize];
+        cell.set(cell.get() + 1);
+    });
+}
+
+pub fn counter_value(counter: Counter) -> u64 {
+    COUNTERS.with(|counters| counters[counter as usize].get())
+}
+
+#[derive(Default)]
+pub struct RenderStats {
+    counts: [u64; COUNTER_COUNT],
+    pub elapsed: Duration,
+}
+
+pub fn take_counters() -> RenderStats {
+    let mut stats = RenderStats::default();
+    COUNTERS.with(|counters| {
+        for (count, cell) in stats.counts.iter_mut().zip(counters.iter()) {
+            *count = cell.replace(0);
+        }
+    });
+    stats
+}
+
+impl RenderStats {
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counts[counter as usize]
+    }
+
+    pub fn rays(&self) -> u64 {
+        self.get(Counter::CameraRays)
+            + self.get(Counter::SecondaryRays)
+            + self.get(Counter::DifferentialRays)
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.rays() as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    // Number of surface or medium interactions per camera path.
+    pub fn average_path_length(&self) -> f64 {
+        let paths = self.get(Counter::CameraRays);
+        if paths > 0 {
+            self.get(Counter::PathVertices) as f64 / paths as f64
+        } else {
+            0.0
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut lines = vec![
+            "render statistics".to_string(),
+            format!("  {:<24}{:.3} s", "time", self.elapsed.as_secs_f64()),
+            format!("  {:<24}{}", "camera rays", self.get(Counter::CameraRays)),
+            format!(
+                "  {:<24}{}",
+                "secondary rays",
+                self.get(Counter::SecondaryRays)
+            ),
+            format!(
+                "  {:<24}{}",
+                "differential rays",
+                self.get(Counter::DifferentialRays)
+            ),
+            format!("  {:<24}{:.0}", "rays per second", self.rays_per_second()),
+            format!(
+                "  {:<24}{:.3}",
+                "average path length",
+                self.average_path_length()
+            ),
+            format!(
+                "  {:<24}{}",
+                "bvh node visits",
+                self.get(Counter::BvhNodeVisits)
+            ),
+            "  intersection tests".to_string(),
+        ];
+        for (counter, name) in &INTERSECTION_TESTS {
+            lines.push(format!("    {:<22}{}", name, self.get(*counter)));
+        }
+        lines.join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        let tests = INTERSECTION_TESTS
+            .iter()
+            .map(|(counter, name)| format!("\"{}\": {}", name, self.get(*counter)))
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!(
+            "{{\"elapsed_seconds\": {}, \"camera_rays\": {}, \"secondary_rays\": {}, \
+             \"differential_rays\": {}, \"rays_per_second\": {}, \
+             \"average_path_length\": {}, \"bvh_node_visits\": {}, \
+             \"intersection_tests\": {{{}}}}}\n",
+            self.elapsed.as_secs_f64(),
+            self.get(Counter::CameraRays),
+            self.get(Counter::SecondaryRays),
+            self.get(Counter::DifferentialRays),
+            self.rays_per_second(),
+            self.average_path_length(),
+            self.get(Counter::BvhNodeVisits),
+            tests
+        )
+    }
+
+    pub fn write_json(&self, filename: &str) -> std::io::Result<()> {
+        File::create(filename)?.write_all(self.to_json().as_bytes())
+    }
+}
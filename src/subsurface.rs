@@ -0,0 +1,155 @@
+use std::rc::Rc;
+
+use libm::*;
+
+use super::aabb::*;
+use super::color::*;
+use super::constants::*;
+use super::hittable::*;
+use super::material::*;
+use super::random::*;
+use super::ray::*;
+use super::sampler::*;
+use super::vec3::*;
+
+// Collisions after which light still inside is considered absorbed.
+const MAX_WALK_STEPS: usize = 256;
+
+// Appearance of a translucent object such as skin, wax or marble, in the working color
+// space.
+#[derive(Copy, Clone)]
+pub struct SubsurfaceSettings {
+    // Color of the object once light has scattered many times inside it.
+    pub albedo: Color,
+    // Average distance light travels inside between collisions, per channel.
+    pub mean_free_path: Color,
+    pub ior: f64,
+}
+
+// A closed object that light enters through a smooth dielectric surface and walks
+// through randomly, colliding with its interior, until it reaches the surface again and
+// leaves, possibly far from where it entered. The object is its own material, so the
+// walk can find the surface.
+pub struct Subsurface<'a> {
+    boundary: Rc<dyn Hittable + 'a>,
+    ior: f64,
+    albedo: Color,
+    extinction: Color,
+    scattering: Color,
+}
+
+impl<'a> Subsurface<'a> {
+    pub fn new(boundary: Rc<dyn Hittable + 'a>, settings: SubsurfaceSettings) -> Subsurface<'a> {
+        let extinction = settings.mean_free_path.map(|distance| 1.0 / distance);
+        Subsurface {
+            boundary,
+            ior: settings.ior,
+            albedo: settings.albedo,
+            extinction,
+            scattering: extinction * settings.albedo.map(single_scattering_albedo),
+        }
+    }
+}
+
+// Albedo of a single collision that makes the object reflect `albedo` after many, from
+// Chiang et al., "Practical and Controllable Subsurface Scattering for Production Path
+// Tracing".
+fn single_scattering_albedo(albedo: f64) -> f64 {
+    let root = 4.09712 + 4.20863 * albedo
+        - (9.59217 + 41.6808 * albedo + 17.7126 * albedo * albedo).sqrt();
+    1.0 - root * root
+}
+
+fn sum(color: Color) -> f64 {
+    color.x + color.y + color.z
+}
+
+impl<'a> Hittable for Subsurface<'a> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let record = self.boundary.hit(ray, t_min, t_max)?;
+        Some(HitRecord {
+            material: self,
+            object_id: self as *const Self as usize,
+            ..record
+        })
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(time0, time1)
+    }
+}
+
+impl<'a> Material for Subsurface<'a> {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        *attenuation = Color::new(1.0, 1.0, 1.0);
+        // Only paths that start inside reach the surface from there; they pass through.
+        if !record.front_face {
+            *scattered = Ray::new(record.p, ray_in.direction, ray_in.time);
+            return true;
+        }
+        let unit_direction = ray_in.direction.unit_vector();
+        let cos_theta = fmin((-unit_direction).dot(&record.normal), 1.0);
+        if Dielectric::schlick(cos_theta, 1.0 / self.ior) > sampler.get_1d() {
+            let reflected = unit_direction.reflect(record.normal);
+            *scattered = Ray::new(record.p, reflected, ray_in.time);
+            return true;
+        }
+        // Collision distances are sampled with the extinction of a channel picked in
+        // proportion to the path's weight in it, and weighted by the density of picking
+        // them with any channel, so that every channel keeps its own mean free path.
+        let mut direction = Vec3::refract(unit_direction, record.normal, 1.0 / self.ior);
+        let mut position = record.p;
+        for _ in 0..MAX_WALK_STEPS {
+            let total = sum(*attenuation);
+            if total <= 0.0 {
+                return false;
+            }
+            let probabilities = *attenuation / total;
+            let u = sampler.get_1d();
+            let channel = if u < probabilities.x {
+                0
+            } else if u < probabilities.x + probabilities.y {
+                1
+            } else {
+                2
+            };
+            let distance = -(1.0 - sampler.get_1d()).ln() / self.extinction[channel];
+            let walk = Ray::new(position, direction, ray_in.time);
+            match self.boundary.hit(&walk, 0.0001, INFINITY) {
+                Some(surface) if surface.t <= distance => {
+                    let transmittance = self.extinction.map(|sigma| (-sigma * surface.t).exp());
+                    *attenuation *= transmittance / sum(probabilities * transmittance);
+                    let cos_theta = fmin((-direction).dot(&surface.normal), 1.0);
+                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                    position = surface.p;
+                    if self.ior * sin_theta > 1.0
+                        || Dielectric::schlick(cos_theta, self.ior) > sampler.get_1d()
+                    {
+                        direction = direction.reflect(surface.normal);
+                        continue;
+                    }
+                    let refracted = Vec3::refract(direction, surface.normal, self.ior);
+                    *scattered = Ray::new(position, refracted, ray_in.time);
+                    return true;
+                }
+                _ => {
+                    let transmittance = self.extinction.map(|sigma| (-sigma * distance).exp());
+                    *attenuation *= self.scattering * transmittance
+                        / sum(probabilities * self.extinction * transmittance);
+                    position = walk.point_at_parameter(distance);
+                    direction = sample_unit_vector(sampler.get_2d());
+                }
+            }
+        }
+        false
+    }
+    fn albedo(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        self.albedo
+    }
+}
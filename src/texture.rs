@@ -1,14 +1,18 @@
+use std::fmt;
+use std::io;
+use std::ops::{Add, AddAssign, Div, Mul};
+
 use crate::color;
-use image::{io::Reader as ImageReader, DynamicImage, GenericImageView, Pixel};
-use num::clamp;
+use image::hdr::HdrDecoder;
+use image::{io::Reader as ImageReader, DynamicImage, GenericImageView, ImageError, ImageFormat};
 
+use super::angles::*;
 use super::color::*;
+use super::colorspace::*;
 use super::perlin::*;
 use super::random::*;
 use super::vec3::*;
 
-use std::cmp::*;
-
 pub struct SolidColor {
     pub color_value: Color,
 }
@@ -32,31 +36,523 @@ impl NoiseTexture {
     }
 }
 
-pub struct ImageTexture {
-    data: Vec<u8>,
+// Reconstruction of an image texture between texels.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+    // Catmull-Rom; the overshoot at sharp edges is clipped at zero.
+    Bicubic,
+}
+
+// Texture coordinates outside [0, 1].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    Clamp,
+}
+
+// Prefiltering over the footprint of a pixel, which camera rays provide.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MipFilter {
+    None,
+    Trilinear,
+    // Several trilinear probes along the longer axis of the footprint.
+    Anisotropic,
+}
+
+// Texture coordinates are scaled and rotated about the center of the texture, then
+// offset.
+#[derive(Copy, Clone, Debug)]
+pub struct TextureSampling {
+    pub filter: TextureFilter,
+    pub wrap: WrapMode,
+    pub mip_filter: MipFilter,
+    pub scale: (f64, f64),
+    // In degrees, counterclockwise.
+    pub rotation: f64,
+    pub offset: (f64, f64),
+}
+
+// Change of the texture coordinates from a pixel to its neighbours along the x and y
+// axes of the image.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct UvFootprint {
+    pub du_dx: f64,
+    pub dv_dx: f64,
+    pub du_dy: f64,
+    pub dv_dy: f64,
+}
+
+// Most probes of anisotropic filtering; longer footprints are blurred along their minor
+// axis instead.
+const MAX_ANISOTROPY: f64 = 8.0;
+
+#[derive(Debug)]
+pub enum TextureError {
+    Open {
+        filename: String,
+        source: io::Error,
+    },
+    Decode {
+        filename: String,
+        source: ImageError,
+    },
+    Empty {
+        filename: String,
+    },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextureError::Open { filename, source } => {
+                write!(f, "cannot open {}: {}", filename, source)
+            }
+            TextureError::Decode { filename, source } => {
+                write!(f, "cannot decode {}: {}", filename, source)
+            }
+            TextureError::Empty { filename } => write!(f, "{} has no pixels", filename),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TextureError::Open { source, .. } => Some(source),
+            TextureError::Decode { source, .. } => Some(source),
+            TextureError::Empty { .. } => None,
+        }
+    }
+}
+
+// An image of any format the image crate reads, as linear sRGB with straight alpha, rows
+// from the top. Integer formats are decoded from the sRGB transfer curve; Radiance HDR
+// files are already linear and opaque. Images without alpha are opaque.
+pub struct LinearImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl LinearImage {
+    pub fn read(filename: &str) -> Result<LinearImage, TextureError> {
+        let open_error = |source| TextureError::Open {
+            filename: filename.to_string(),
+            source,
+        };
+        let decode_error = |source| TextureError::Decode {
+            filename: filename.to_string(),
+            source,
+        };
+        let reader = ImageReader::open(filename)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(open_error)?;
+        let image = if reader.format() == Some(ImageFormat::Hdr) {
+            // Decoding through the reader would tone map to 8 bits.
+            let decoder = HdrDecoder::new(reader.into_inner()).map_err(decode_error)?;
+            let metadata = decoder.metadata();
+            LinearImage {
+                width: metadata.width as usize,
+                height: metadata.height as usize,
+                pixels: decoder
+                    .read_image_hdr()
+                    .map_err(decode_error)?
+                    .iter()
+                    .map(|pixel| [pixel[0], pixel[1], pixel[2], 1.0])
+                    .collect(),
+            }
+        } else {
+            LinearImage::from_dynamic(&reader.decode().map_err(decode_error)?)
+        };
+        if image.pixels.is_empty() {
+            return Err(TextureError::Empty {
+                filename: filename.to_string(),
+            });
+        }
+        Ok(image)
+    }
+
+    fn from_dynamic(image: &DynamicImage) -> LinearImage {
+        let decode = |value: f64| srgb_decode(value) as f32;
+        let pixels = match image {
+            DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_) => image
+                .to_rgba16()
+                .pixels()
+                .map(|pixel| {
+                    let value = |channel: usize| pixel[channel] as f64 / 65535.0;
+                    [
+                        decode(value(0)),
+                        decode(value(1)),
+                        decode(value(2)),
+                        value(3) as f32,
+                    ]
+                })
+                .collect(),
+            _ => image
+                .to_rgba8()
+                .pixels()
+                .map(|pixel| {
+                    let value = |channel: usize| pixel[channel] as f64 / 255.0;
+                    [
+                        decode(value(0)),
+                        decode(value(1)),
+                        decode(value(2)),
+                        value(3) as f32,
+                    ]
+                })
+                .collect(),
+        };
+        LinearImage {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels,
+        }
+    }
+}
+
+// Color premultiplied by alpha, so that filtering does not bleed the color of
+// transparent texels into opaque ones.
+#[derive(Copy, Clone, Default)]
+struct Texel {
+    color: Color,
+    alpha: f64,
+}
+
+impl Texel {
+    fn straight_color(&self) -> Color {
+        if self.alpha > 0.0 {
+            self.color / self.alpha
+        } else {
+            Color::default()
+        }
+    }
+}
+
+impl Add for Texel {
+    type Output = Texel;
+    fn add(self, rhs: Texel) -> Texel {
+        Texel {
+            color: self.color + rhs.color,
+            alpha: self.alpha + rhs.alpha,
+        }
+    }
+}
+
+impl AddAssign for Texel {
+    fn add_assign(&mut self, rhs: Texel) {
+        *self = *self + rhs;
+    }
+}
+
+impl Mul<Texel> for f64 {
+    type Output = Texel;
+    fn mul(self, rhs: Texel) -> Texel {
+        Texel {
+            color: self * rhs.color,
+            alpha: self * rhs.alpha,
+        }
+    }
+}
+
+impl Div<f64> for Texel {
+    type Output = Texel;
+    fn div(self, rhs: f64) -> Texel {
+        Texel {
+            color: self.color / rhs,
+            alpha: self.alpha / rhs,
+        }
+    }
+}
+
+struct MipLevel {
+    texels: Vec<Texel>,
     width: usize,
     height: usize,
-    bytes_per_scanline: usize,
+}
+
+// Texels are linear values in the working color space. The first level is the image;
+// each further one halves its size, down to a single texel.
+pub struct ImageTexture {
+    levels: Vec<MipLevel>,
+    sampling: TextureSampling,
+    // Linear part and translation of the texture coordinate transform.
+    uv_matrix: [[f64; 2]; 2],
+    uv_offset: (f64, f64),
 }
 
 impl ImageTexture {
-    fn bytes_per_pixel() -> usize {
-        3
+    pub fn new(
+        filename: &str,
+        working_space: ColorSpace,
+        sampling: TextureSampling,
+    ) -> Result<ImageTexture, TextureError> {
+        let image = LinearImage::read(filename)?;
+        let to_working = conversion_matrix(ColorSpace::Srgb, working_space);
+        let mut levels = vec![MipLevel {
+            texels: image
+                .pixels
+                .iter()
+                .map(|pixel| {
+                    let color = Color::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64);
+                    let alpha = pixel[3] as f64;
+                    Texel {
+                        color: alpha * transform_color(&to_working, color),
+                        alpha,
+                    }
+                })
+                .collect(),
+            width: image.width,
+            height: image.height,
+        }];
+        if sampling.mip_filter != MipFilter::None {
+            while let Some(level) = levels.last().and_then(MipLevel::downsample) {
+                levels.push(level);
+            }
+        }
+        let (sin, cos) = degrees_to_radians(sampling.rotation).sin_cos();
+        let uv_matrix = [
+            [cos * sampling.scale.0, -sin * sampling.scale.1],
+            [sin * sampling.scale.0, cos * sampling.scale.1],
+        ];
+        let uv_offset = (
+            0.5 - uv_matrix[0][0] * 0.5 - uv_matrix[0][1] * 0.5 + sampling.offset.0,
+            0.5 - uv_matrix[1][0] * 0.5 - uv_matrix[1][1] * 0.5 + sampling.offset.1,
+        );
+        Ok(ImageTexture {
+            levels,
+            sampling,
+            uv_matrix,
+            uv_offset,
+        })
+    }
+
+    fn transform(&self, u: f64, v: f64) -> (f64, f64) {
+        (
+            self.uv_matrix[0][0] * u + self.uv_matrix[0][1] * v + self.uv_offset.0,
+            self.uv_matrix[1][0] * u + self.uv_matrix[1][1] * v + self.uv_offset.1,
+        )
+    }
+
+    // Texture coordinates at `u`, `v` reconstructed on one level with the filter.
+    fn lookup(&self, level: usize, u: f64, v: f64) -> Texel {
+        let level = &self.levels[level];
+        let wrap = self.sampling.wrap;
+        // Rows are stored from the top of the image.
+        let s = u * level.width as f64;
+        let t = (1.0 - v) * level.height as f64;
+        match self.sampling.filter {
+            TextureFilter::Nearest => level.texel(wrap, s.floor() as i64, t.floor() as i64),
+            TextureFilter::Bilinear => {
+                let (x, y) = ((s - 0.5).floor(), (t - 0.5).floor());
+                let (fx, fy) = (s - 0.5 - x, t - 0.5 - y);
+                let (x, y) = (x as i64, y as i64);
+                (1.0 - fy)
+                    * ((1.0 - fx) * level.texel(wrap, x, y) + fx * level.texel(wrap, x + 1, y))
+                    + fy * ((1.0 - fx) * level.texel(wrap, x, y + 1)
+                        + fx * level.texel(wrap, x + 1, y + 1))
+            }
+            TextureFilter::Bicubic => {
+                let (x, y) = ((s - 0.5).floor(), (t - 0.5).floor());
+                let (wx, wy) = (
+                    catmull_rom_weights(s - 0.5 - x),
+                    catmull_rom_weights(t - 0.5 - y),
+                );
+                let (x, y) = (x as i64, y as i64);
+                let mut texel = Texel::default();
+                for (j, weight_y) in wy.iter().enumerate() {
+                    for (i, weight_x) in wx.iter().enumerate() {
+                        texel += weight_x
+                            * weight_y
+                            * level.texel(wrap, x + i as i64 - 1, y + j as i64 - 1);
+                    }
+                }
+                Texel {
+                    color: texel.color.map(|value| value.max(0.0)),
+                    alpha: texel.alpha.clamp(0.0, 1.0),
+                }
+            }
+        }
+    }
+
+    // Blend of the two levels whose texels are closest to `width` texels of the first.
+    fn trilinear(&self, u: f64, v: f64, width: f64) -> Texel {
+        let last = (self.levels.len() - 1) as f64;
+        let level = width.max(1.0).log2().min(last);
+        let lower = level.floor();
+        let fraction = level - lower;
+        let texel = self.lookup(lower as usize, u, v);
+        if fraction > 0.0 {
+            (1.0 - fraction) * texel + fraction * self.lookup(lower as usize + 1, u, v)
+        } else {
+            texel
+        }
+    }
+
+    fn filtered(&self, u: f64, v: f64, footprint: &UvFootprint) -> Texel {
+        let (u, v) = self.transform(u, v);
+        if self.sampling.mip_filter == MipFilter::None {
+            return self.lookup(0, u, v);
+        }
+        // Axes of the footprint in texels of the first level.
+        let (width, height) = (self.levels[0].width as f64, self.levels[0].height as f64);
+        let axis = |du: f64, dv: f64| {
+            let (du, dv) = (
+                self.uv_matrix[0][0] * du + self.uv_matrix[0][1] * dv,
+                self.uv_matrix[1][0] * du + self.uv_matrix[1][1] * dv,
+            );
+            (du, dv, (du * width).hypot(dv * height))
+        };
+        let x_axis = axis(footprint.du_dx, footprint.dv_dx);
+        let y_axis = axis(footprint.du_dy, footprint.dv_dy);
+        let (major, minor) = if x_axis.2 >= y_axis.2 {
+            (x_axis, y_axis)
+        } else {
+            (y_axis, x_axis)
+        };
+        if self.sampling.mip_filter == MipFilter::Trilinear {
+            return self.trilinear(u, v, major.2);
+        }
+        let probes = (major.2 / minor.2.max(1e-8))
+            .ceil()
+            .clamp(1.0, MAX_ANISOTROPY);
+        let width = minor.2.max(major.2 / MAX_ANISOTROPY);
+        let mut texel = Texel::default();
+        for i in 0..probes as usize {
+            let position = (i as f64 + 0.5) / probes - 0.5;
+            texel += self.trilinear(u + position * major.0, v + position * major.1, width);
+        }
+        texel / probes
+    }
+}
+
+impl MipLevel {
+    fn texel(&self, wrap: WrapMode, x: i64, y: i64) -> Texel {
+        let x = wrap_index(wrap, x, self.width);
+        let y = wrap_index(wrap, y, self.height);
+        self.texels[y * self.width + x]
+    }
+
+    // Box filtered to half the size; the last row and column of odd sizes take in the
+    // leftover texels.
+    fn downsample(&self) -> Option<MipLevel> {
+        if self.width == 1 && self.height == 1 {
+            return None;
+        }
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let rows = (y * self.height / height)..((y + 1) * self.height / height);
+            for x in 0..width {
+                let columns = (x * self.width / width)..((x + 1) * self.width / width);
+                let mut sum = Texel::default();
+                for row in rows.clone() {
+                    for column in columns.clone() {
+                        sum += self.texels[row * self.width + column];
+                    }
+                }
+                texels.push(sum / (rows.len() * columns.len()) as f64);
+            }
+        }
+        Some(MipLevel {
+            texels,
+            width,
+            height,
+        })
+    }
+}
+
+fn wrap_index(wrap: WrapMode, index: i64, size: usize) -> usize {
+    let size = size as i64;
+    let wrapped = match wrap {
+        WrapMode::Repeat => index.rem_euclid(size),
+        WrapMode::Mirror => {
+            let period = index.rem_euclid(2 * size);
+            if period < size {
+                period
+            } else {
+                2 * size - 1 - period
+            }
+        }
+        WrapMode::Clamp => index.clamp(0, size - 1),
+    };
+    wrapped as usize
+}
+
+fn catmull_rom_weights(t: f64) -> [f64; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        0.5 * (-t3 + 2.0 * t2 - t),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
+
+impl TextureFilter {
+    pub fn from_name(name: &str) -> Option<TextureFilter> {
+        match name {
+            "nearest" => Some(TextureFilter::Nearest),
+            "bilinear" => Some(TextureFilter::Bilinear),
+            "bicubic" => Some(TextureFilter::Bicubic),
+            _ => None,
+        }
     }
+}
 
-    pub fn new(filename: &str) -> ImageTexture {
-        let img = ImageReader::open(filename).unwrap().decode().unwrap();
-        ImageTexture {
-            data: img.to_bytes(),
-            width: img.dimensions().0 as usize,
-            height: img.dimensions().1 as usize,
-            bytes_per_scanline: Self::bytes_per_pixel() * (img.dimensions().0 as usize),
+impl WrapMode {
+    pub fn from_name(name: &str) -> Option<WrapMode> {
+        match name {
+            "repeat" => Some(WrapMode::Repeat),
+            "mirror" => Some(WrapMode::Mirror),
+            "clamp" => Some(WrapMode::Clamp),
+            _ => None,
+        }
+    }
+}
+
+impl MipFilter {
+    pub fn from_name(name: &str) -> Option<MipFilter> {
+        match name {
+            "none" => Some(MipFilter::None),
+            "trilinear" => Some(MipFilter::Trilinear),
+            "anisotropic" => Some(MipFilter::Anisotropic),
+            _ => None,
+        }
+    }
+}
+
+impl Default for TextureSampling {
+    fn default() -> Self {
+        TextureSampling {
+            filter: TextureFilter::Nearest,
+            wrap: WrapMode::Clamp,
+            mip_filter: MipFilter::None,
+            scale: (1.0, 1.0),
+            rotation: 0.0,
+            offset: (0.0, 0.0),
         }
     }
 }
 
 pub trait Texture {
     fn value(&self, u: f64, v: f64, point: &Vec3) -> Color;
+    // Average over the footprint of a pixel; textures that are not prefiltered take the
+    // value at its center.
+    fn filtered_value(&self, u: f64, v: f64, _footprint: &UvFootprint, point: &Vec3) -> Color {
+        self.value(u, v, point)
+    }
+    // Coverage of the surface, from transparent at 0 to opaque at 1.
+    fn alpha(&self, _u: f64, _v: f64, _point: &Vec3) -> f64 {
+        1.0
+    }
 }
 
 impl Texture for SolidColor {
@@ -87,20 +583,18 @@ impl Texture for NoiseTexture {
 
 impl Texture for ImageTexture {
     fn value(&self, u: f64, v: f64, _point: &Vec3) -> Color {
-        let uu = clamp(u, 0.0, 1.0);
-        let vv = 1.0 - clamp(v, 0.0, 1.0);
-        let col_index = min((uu * self.width as f64) as usize, self.width - 1);
-        let row_index = min((vv * self.height as f64) as usize, self.height - 1);
-        let pixel_index =
-            (self.bytes_per_scanline * row_index) + (col_index * Self::bytes_per_pixel());
-        let color_scale = 1.0 / 255.0;
-        Color::new(
-            color_scale * self.data[pixel_index] as f64,
-            color_scale * self.data[pixel_index + 1] as f64,
-            color_scale * self.data[pixel_index + 2] as f64,
-        )
+        let (u, v) = self.transform(u, v);
+        self.lookup(0, u, v).straight_color()
+    }
+    fn filtered_value(&self, u: f64, v: f64, footprint: &UvFootprint, _point: &Vec3) -> Color {
+        self.filtered(u, v, footprint).straight_color()
+    }
+    fn alpha(&self, u: f64, v: f64, _point: &Vec3) -> f64 {
+        let (u, v) = self.transform(u, v);
+        self.lookup(0, u, v).alpha
     }
 }
+
 pub struct Textures {
     pub v_solid_colors: Vec<SolidColor>,
     pub v_checker_textures: Vec<CheckerTexture>,
@@ -109,31 +603,27 @@ pub struct Textures {
 }
 
 impl<'a> Textures {
-    pub fn new() -> Textures {
-        Textures {
+    // Colors below are linear sRGB and converted to the working color space.
+    pub fn new(
+        working_space: ColorSpace,
+        sampling: TextureSampling,
+    ) -> Result<Textures, TextureError> {
+        let to_working = conversion_matrix(ColorSpace::Srgb, working_space);
+        let solid_color = |r: f64, g: f64, b: f64| SolidColor {
+            color_value: transform_color(&to_working, Color::new(r, g, b)),
+        };
+        Ok(Textures {
             v_solid_colors: vec![
-                SolidColor {
-                    color_value: Vec3::new(0.48, 0.83, 0.53),
-                },
-                SolidColor {
-                    color_value: Vec3::new(7.0, 7.0, 7.0),
-                },
-                SolidColor {
-                    color_value: Vec3::new(0.7, 0.3, 0.1),
-                },
-                SolidColor {
-                    color_value: Vec3::new(0.2, 0.4, 0.9),
-                },
-                SolidColor {
-                    color_value: Vec3::new(1.0, 1.0, 1.0),
-                },
-                SolidColor {
-                    color_value: Vec3::new(0.73, 0.73, 0.73),
-                },
+                solid_color(0.48, 0.83, 0.53),
+                solid_color(7.0, 7.0, 7.0),
+                solid_color(0.7, 0.3, 0.1),
+                solid_color(0.2, 0.4, 0.9),
+                solid_color(1.0, 1.0, 1.0),
+                solid_color(0.73, 0.73, 0.73),
             ],
             v_checker_textures: Default::default(),
             v_noise_textures: vec![NoiseTexture::new(0.1)],
-            v_image_textures: vec![ImageTexture::new("earthmap.jpeg")],
-        }
+            v_image_textures: vec![ImageTexture::new("earthmap.jpeg", working_space, sampling)?],
+        })
     }
 }
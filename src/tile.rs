@@ -0,0 +1,132 @@
+use std::cmp::Ordering;
+
+// Pixel rectangle [x0, x1) x [y0, y1) in film coordinates (origin at the bottom-left).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PixelBounds {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    Hilbert,
+}
+
+impl PixelBounds {
+    pub fn new(x0: u32, y0: u32, x1: u32, y1: u32) -> PixelBounds {
+        PixelBounds { x0, y0, x1, y1 }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x0 >= self.x1 || self.y0 >= self.y1
+    }
+
+    pub fn intersect(&self, other: &PixelBounds) -> PixelBounds {
+        let x0 = self.x0.max(other.x0);
+        let y0 = self.y0.max(other.y0);
+        PixelBounds {
+            x0,
+            y0,
+            x1: self.x1.min(other.x1).max(x0),
+            y1: self.y1.min(other.y1).max(y0),
+        }
+    }
+
+    // Pixels in image order: top row first, left to right.
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let bounds = *self;
+        (bounds.y0..bounds.y1)
+            .rev()
+            .flat_map(move |y| (bounds.x0..bounds.x1).map(move |x| (x, y)))
+    }
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+}
+
+// Splits `bounds` into tiles of at most `tile_size` pixels on a side, in the requested
+// order. Tile rows are numbered from the top of the image.
+pub fn make_tiles(bounds: &PixelBounds, tile_size: u32, order: TileOrder) -> Vec<PixelBounds> {
+    let tile_size = tile_size.max(1);
+    let columns = bounds.width().div_ceil(tile_size);
+    let rows = bounds.height().div_ceil(tile_size);
+    let mut cells: Vec<(u32, u32)> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let center_x = (columns as f64 - 1.0) * 0.5;
+            let center_y = (rows as f64 - 1.0) * 0.5;
+            let key = |&(column, row): &(u32, u32)| {
+                let dx = column as f64 - center_x;
+                let dy = row as f64 - center_y;
+                (dx.abs().max(dy.abs()).round(), dy.atan2(dx))
+            };
+            cells.sort_by(|a, b| {
+                let (ring_a, angle_a) = key(a);
+                let (ring_b, angle_b) = key(b);
+                ring_a
+                    .partial_cmp(&ring_b)
+                    .unwrap_or(Ordering::Equal)
+                    .then(angle_a.partial_cmp(&angle_b).unwrap_or(Ordering::Equal))
+            });
+        }
+        TileOrder::Hilbert => {
+            let side = columns.max(rows).next_power_of_two();
+            cells.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+        }
+    }
+    cells
+        .into_iter()
+        .map(|(column, row)| {
+            let x0 = bounds.x0 + column * tile_size;
+            let y1 = bounds.y1 - row * tile_size;
+            PixelBounds {
+                x0,
+                y0: y1.saturating_sub(tile_size).max(bounds.y0),
+                x1: (x0 + tile_size).min(bounds.x1),
+                y1,
+            }
+        })
+        .collect()
+}
+
+fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0u64;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = ((x & s) > 0) as u32;
+        let ry = ((y & s) > 0) as u32;
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
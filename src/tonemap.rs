@@ -0,0 +1,161 @@
+use super::color::*;
+use super::colorspace::*;
+use super::ppm::*;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ToneMapper {
+    Clamp,
+    Reinhard,
+    ExtendedReinhard,
+    Aces,
+    Hable,
+    Agx,
+}
+
+// Applied to linear values in the display's primaries when they are quantized to 8 bits:
+// exposure first, then the tone curve, then the display's transfer function.
+#[derive(Copy, Clone, Debug)]
+pub struct ToneMapping {
+    pub operator: ToneMapper,
+    // In stops: every unit doubles the brightness.
+    pub exposure: f64,
+    // Luminance mapped to white by the extended Reinhard operator.
+    pub white_point: f64,
+    pub display_space: ColorSpace,
+}
+
+impl ToneMapper {
+    pub fn from_name(name: &str) -> Option<ToneMapper> {
+        match name {
+            "clamp" => Some(ToneMapper::Clamp),
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "reinhard-extended" => Some(ToneMapper::ExtendedReinhard),
+            "aces" => Some(ToneMapper::Aces),
+            "hable" => Some(ToneMapper::Hable),
+            "agx" => Some(ToneMapper::Agx),
+            _ => None,
+        }
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            operator: ToneMapper::Clamp,
+            exposure: 0.0,
+            white_point: 4.0,
+            display_space: ColorSpace::Srgb,
+        }
+    }
+}
+
+impl ToneMapping {
+    // Linear display values in [0, 1].
+    pub fn map(&self, color: Color) -> Color {
+        let color = color.map(|value| value.max(0.0)) * 2f64.powf(self.exposure);
+        let mapped = match self.operator {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapper::ExtendedReinhard => {
+                let white_squared = self.white_point * self.white_point;
+                scale_luminance(color, |l| l * (1.0 + l / white_squared) / (1.0 + l))
+            }
+            ToneMapper::Aces => self.aces(color),
+            ToneMapper::Hable => {
+                let white_scale = 1.0 / hable_curve(HABLE_WHITE);
+                color.map(|value| hable_curve(HABLE_EXPOSURE_BIAS * value) * white_scale)
+            }
+            ToneMapper::Agx => agx(color),
+        };
+        mapped.map(|value| value.clamp(0.0, 1.0))
+    }
+
+    // The fit expects Rec.709 primaries, so other displays go through linear sRGB.
+    fn aces(&self, color: Color) -> Color {
+        if self.display_space == ColorSpace::Srgb {
+            return aces(color);
+        }
+        let to_srgb = conversion_matrix(self.display_space, ColorSpace::Srgb);
+        let from_srgb = conversion_matrix(ColorSpace::Srgb, self.display_space);
+        transform_color(&from_srgb, aces(transform_color(&to_srgb, color)))
+    }
+
+    pub fn encode(&self, color: Color) -> RGB {
+        let mapped = self.map(color);
+        let quantize = |value: f64| (255.0 * self.display_space.encode(value) + 0.5) as u8;
+        RGB {
+            r: quantize(mapped.x),
+            g: quantize(mapped.y),
+            b: quantize(mapped.z),
+        }
+    }
+}
+
+fn scale_luminance(color: Color, curve: impl Fn(f64) -> f64) -> Color {
+    let value = luminance(&color);
+    if value > 0.0 {
+        color * (curve(value) / value)
+    } else {
+        color
+    }
+}
+
+// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms.
+const ACES_INPUT: Matrix3 = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+const ACES_OUTPUT: Matrix3 = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn aces(color: Color) -> Color {
+    let fit =
+        |v: f64| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+    transform_color(&ACES_OUTPUT, transform_color(&ACES_INPUT, color).map(fit))
+}
+
+// John Hable's filmic curve from Uncharted 2.
+const HABLE_EXPOSURE_BIAS: f64 = 2.0;
+const HABLE_WHITE: f64 = 11.2;
+
+fn hable_curve(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+// Minimal AgX: Benjamin Wrensch's polynomial fit of Troy Sobotka's base contrast curve.
+const AGX_INSET: Matrix3 = [
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+];
+const AGX_OUTSET: Matrix3 = [
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+];
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+
+fn agx(color: Color) -> Color {
+    let contrast = |v: f64| {
+        let encoded = (v.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV) - AGX_MIN_EV)
+            / (AGX_MAX_EV - AGX_MIN_EV);
+        let x2 = encoded * encoded;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * encoded + 31.96 * x4 - 6.868 * x2 * encoded
+            + 0.4298 * x2
+            + 0.1191 * encoded
+            - 0.00232
+    };
+    let curved = transform_color(
+        &AGX_OUTSET,
+        transform_color(&AGX_INSET, color).map(contrast),
+    );
+    // The curve produces display-encoded values; return to linear.
+    curved.map(|value| value.max(0.0).powf(2.2))
+}
@@ -22,6 +22,90 @@ pub struct RotationY<'a> {
     maybe_aabb: Option<AABB>,
 }
 
+// Places an object with a linear map followed by a translation. The parameter along a
+// ray is unchanged by the map, so hits keep their `t`.
+pub struct AffineTransform<'a> {
+    ptr: Rc<dyn Hittable + 'a>,
+    to_world: Transform,
+    to_local: Transform,
+}
+
+type Matrix = [[f64; 3]; 3];
+
+// A linear map followed by a translation, kept with the inverse of the map.
+#[derive(Copy, Clone)]
+pub struct Transform {
+    matrix: Matrix,
+    inverse: Matrix,
+    translation: Vec3,
+}
+
+impl Transform {
+    // Returns None when the map is singular.
+    pub fn new(matrix: Matrix, translation: Vec3) -> Option<Transform> {
+        let inverse = invert(&matrix)?;
+        Some(Transform {
+            matrix,
+            inverse,
+            translation,
+        })
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+            translation: -multiply(&self.inverse, self.translation),
+        }
+    }
+
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        multiply(&self.matrix, point) + self.translation
+    }
+
+    pub fn transform_vector(&self, vector: Vec3) -> Vec3 {
+        multiply(&self.matrix, vector)
+    }
+
+    // Normals transform with the inverse transpose; the result is not normalized.
+    pub fn transform_normal(&self, normal: Vec3) -> Vec3 {
+        let m = &self.inverse;
+        Vec3::new(
+            m[0][0] * normal.x + m[1][0] * normal.y + m[2][0] * normal.z,
+            m[0][1] * normal.x + m[1][1] * normal.y + m[2][1] * normal.z,
+            m[0][2] * normal.x + m[1][2] * normal.y + m[2][2] * normal.z,
+        )
+    }
+}
+
+fn multiply(m: &Matrix, v: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+    )
+}
+
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum::<f64>();
+    let mut inverse = [[0.0; 3]; 3];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = cofactor(j, i) / determinant;
+        }
+    }
+    if inverse.iter().flatten().all(|value| value.is_finite()) {
+        Some(inverse)
+    } else {
+        None
+    }
+}
+
 impl<'a> RotationY<'a> {
     pub fn new(ptr: Rc<dyn Hittable + 'a>, angle_deg: f64) -> RotationY<'a> {
         let radians = degrees_to_radians(angle_deg);
@@ -67,6 +151,16 @@ impl<'a> RotationY<'a> {
     }
 }
 
+impl<'a> AffineTransform<'a> {
+    pub fn new(ptr: Rc<dyn Hittable + 'a>, to_world: Transform) -> AffineTransform<'a> {
+        AffineTransform {
+            ptr,
+            to_world,
+            to_local: to_world.inverse(),
+        }
+    }
+}
+
 impl<'a> Hittable for Translate<'a> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let moved_ray = Ray::new(ray.origin - self.offset, ray.direction, ray.time);
@@ -83,11 +177,10 @@ impl<'a> Hittable for Translate<'a> {
         }
     }
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
-        if let Some(aabb) = self.ptr.bounding_box(time0, time1) {
-            Some(aabb)
-        } else {
-            None
-        }
+        self.ptr.bounding_box(time0, time1).map(|aabb| AABB {
+            minimum: aabb.minimum + self.offset,
+            maximum: aabb.maximum + self.offset,
+        })
     }
 }
 
@@ -131,3 +224,52 @@ impl<'a> Hittable for RotationY<'a> {
         self.maybe_aabb.clone()
     }
 }
+
+impl<'a> Hittable for AffineTransform<'a> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let local_ray = Ray::new(
+            self.to_local.transform_point(ray.origin),
+            self.to_local.transform_vector(ray.direction),
+            ray.time,
+        );
+        let record = self.ptr.hit(&local_ray, t_min, t_max)?;
+        let n = self.to_world.transform_normal(record.normal);
+        let (front_facing, normal) = make_facing_normal(ray, n.unit_vector());
+        Some(HitRecord {
+            p: ray.point_at_parameter(record.t),
+            front_face: front_facing,
+            normal,
+            ..record
+        })
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        let aabb = self.ptr.bounding_box(time0, time1)?;
+        let mut minimum = Vec3::new(INFINITY, INFINITY, INFINITY);
+        let mut maximum = Vec3::new(-INFINITY, -INFINITY, -INFINITY);
+        for corner in 0..8 {
+            let local = Vec3::new(
+                if corner & 1 == 0 {
+                    aabb.minimum.x
+                } else {
+                    aabb.maximum.x
+                },
+                if corner & 2 == 0 {
+                    aabb.minimum.y
+                } else {
+                    aabb.maximum.y
+                },
+                if corner & 4 == 0 {
+                    aabb.minimum.z
+                } else {
+                    aabb.maximum.z
+                },
+            );
+            let world = self.to_world.transform_point(local);
+            for c in 0..3 {
+                minimum[c] = fmin(minimum[c], world[c]);
+                maximum[c] = fmax(maximum[c], world[c]);
+            }
+        }
+        Some(AABB { minimum, maximum })
+    }
+}
@@ -1,9 +1,10 @@
 use super::constants::*;
 use super::vec3::*;
 
+// Texture coordinates at a point of the surface whose outward normal is given.
 pub trait UvOp {
-    fn get_u(&self, p: Vec3) -> f64;
-    fn get_v(&self, p: Vec3) -> f64;
+    fn get_u(&self, point: Vec3, outward_normal: Vec3) -> f64;
+    fn get_v(&self, point: Vec3, outward_normal: Vec3) -> f64;
 }
 
 pub fn get_sphere_u(p: Vec3) -> f64 {
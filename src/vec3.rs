@@ -68,6 +68,11 @@ impl Vec3 {
         )
     }
 
+    // Applies `f` to every component.
+    pub fn map(&self, f: impl Fn(f64) -> f64) -> Vec3 {
+        Vec3::new(f(self.x), f(self.y), f(self.z))
+    }
+
     pub fn make_color(&self) -> Self {
         0.5 * Self::new(self.x + 1.0, self.y + 1.0, self.z + 1.0)
     }
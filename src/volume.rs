@@ -5,10 +5,83 @@ use libm::*;
 use crate::{random::random_double, vec3::Vec3};
 
 use super::aabb::*;
+use super::color::*;
 use super::constants::*;
 use super::hittable::*;
 use super::material::*;
+use super::perlin::*;
 use super::ray::*;
+use super::sampler::*;
+use super::stats::*;
+
+// Source of the density of a heterogeneous medium.
+pub trait Density {
+    fn density(&self, point: &Vec3) -> f64;
+    // Upper bound of the density anywhere, for delta tracking.
+    fn max_density(&self) -> f64;
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DensityKind {
+    Uniform,
+    Noise,
+    Cloud,
+}
+
+// Constant density.
+pub struct UniformDensity {
+    density: f64,
+}
+
+// Density at the vertices of a regular grid spanning a box, trilinearly interpolated and
+// zero outside the box.
+pub struct GridDensity {
+    minimum: Vec3,
+    maximum: Vec3,
+    size: [usize; 3],
+    values: Vec<f64>,
+    max_value: f64,
+}
+
+// Perlin turbulence; `scale` is the noise frequency in world units.
+pub struct NoiseDensity {
+    noise: Perlin,
+    scale: f64,
+}
+
+// Optical properties of a heterogeneous medium per unit density, in the working color
+// space. Per-channel extinction makes the medium tint what is seen through it.
+#[derive(Copy, Clone)]
+pub struct MediumSettings {
+    pub density: DensityKind,
+    pub extinction: Color,
+    // Fraction of the extinction that scatters; the rest is absorbed.
+    pub albedo: Color,
+    // Radiance added where light is absorbed, as in fire.
+    pub emission: Color,
+}
+
+// A medium of varying density inside a closed boundary, sampled with delta tracking.
+// Chromatic media use spectral tracking (Kutz et al.): collisions are sampled against the
+// largest extinction and the hit carries a weight for the other channels. The weight of
+// a ray that leaves the medium is carried by a hit at the exit, so paths through chromatic
+// media take extra vertices.
+pub struct HeterogeneousMedium<'a> {
+    boundary: Rc<dyn Hittable + 'a>,
+    phase_function: &'a dyn Material,
+    density: Box<dyn Density>,
+    settings: MediumSettings,
+    emission: VolumeEmission,
+    chromatic: bool,
+}
+
+// Ends the path at an absorption event, adding the medium's emission.
+struct VolumeEmission {
+    color: Color,
+}
+
+// Continues the ray unchanged where it leaves a chromatic medium.
+struct MediumExit;
 
 pub struct ConstantMedium<'a> {
     boundary: Rc<dyn Hittable + 'a>,
@@ -26,42 +99,648 @@ impl<'a> ConstantMedium<'a> {
     }
 }
 
+// Separation between a crossing of the boundary and the search for the next one.
+const CROSSING_EPSILON: f64 = 0.0001;
+
+// Parameter intervals of the ray inside a closed boundary, in order along the ray and
+// clipped to [t_min, t_max]. Crossings are counted from the far side of the ray, every one
+// toggling between outside and inside, so the boundary may be non-convex or made of
+// several pieces and the orientation of its normals does not matter. A boundary that is
+// not closed along the ray leaves a last crossing without a partner, which is ignored:
+// open boundaries never enclose the rest of the ray.
+struct BoundaryIntervals<'b> {
+    boundary: &'b dyn Hittable,
+    ray: &'b Ray,
+    t_min: f64,
+    t_max: f64,
+    // Where the search for the next entry starts.
+    t_next: f64,
+}
+
+impl<'b> BoundaryIntervals<'b> {
+    fn new(boundary: &'b dyn Hittable, ray: &'b Ray, t_min: f64, t_max: f64) -> Self {
+        BoundaryIntervals {
+            boundary,
+            ray,
+            t_min,
+            t_max,
+            t_next: -INFINITY,
+        }
+    }
+}
+
+impl<'b> Iterator for BoundaryIntervals<'b> {
+    type Item = (f64, f64);
+
+    fn next(&mut self) -> Option<(f64, f64)> {
+        loop {
+            let entry = self.boundary.hit(self.ray, self.t_next, INFINITY)?;
+            if entry.t >= self.t_max {
+                return None;
+            }
+            let exit = self
+                .boundary
+                .hit(self.ray, entry.t + CROSSING_EPSILON, INFINITY)?;
+            self.t_next = exit.t + CROSSING_EPSILON;
+            let t_entry = fmax(fmax(entry.t, self.t_min), 0.0);
+            let t_exit = fmin(exit.t, self.t_max);
+            if t_entry < t_exit {
+                return Some((t_entry, t_exit));
+            }
+        }
+    }
+}
+
 impl<'a> Hittable for ConstantMedium<'a> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if let Some(mut record1) = self.boundary.hit(ray, -INFINITY, INFINITY) {
-            if let Some(mut record2) = self.boundary.hit(ray, record1.t + 0.0001, INFINITY) {
-                record1.t = fmax(record1.t, t_min);
-                record2.t = fmin(record2.t, t_max);
-                if record1.t >= record2.t {
-                    return None;
+        count(Counter::ConstantMediumTests);
+        let mut intervals = BoundaryIntervals::new(self.boundary.as_ref(), ray, t_min, t_max);
+        let first = intervals.next()?;
+        let ray_length = ray.direction.length();
+        // Distance to the collision, counted inside the boundary only.
+        let mut hit_distance = self.neg_inv_density * random_double().ln();
+        let mut t = None;
+        for (t_entry, t_exit) in std::iter::once(first).chain(intervals) {
+            let distance_inside_interval = (t_exit - t_entry) * ray_length;
+            if hit_distance <= distance_inside_interval {
+                t = Some(t_entry + hit_distance / ray_length);
+                break;
+            }
+            hit_distance -= distance_inside_interval;
+        }
+        let t = t?;
+        Some(HitRecord {
+            t: t,
+            p: ray.point_at_parameter(t),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            front_face: true,
+            u: Default::default(),
+            v: Default::default(),
+            material: self.phase_function,
+            object_id: self as *const Self as usize,
+            weight: Color::new(1.0, 1.0, 1.0),
+            footprint: None,
+        })
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(time0, time1)
+    }
+}
+
+impl DensityKind {
+    pub fn from_name(name: &str) -> Option<DensityKind> {
+        match name {
+            "uniform" => Some(DensityKind::Uniform),
+            "noise" => Some(DensityKind::Noise),
+            "cloud" => Some(DensityKind::Cloud),
+            _ => None,
+        }
+    }
+
+    // Densities around 1 inside the box.
+    pub fn make(&self, minimum: Vec3, maximum: Vec3) -> Box<dyn Density> {
+        match self {
+            DensityKind::Uniform => Box::new(UniformDensity { density: 1.0 }),
+            DensityKind::Noise => Box::new(NoiseDensity {
+                noise: Perlin::new(),
+                scale: 8.0 / (maximum.x - minimum.x),
+            }),
+            DensityKind::Cloud => Box::new(GridDensity::cloud(minimum, maximum, 32)),
+        }
+    }
+}
+
+impl Density for UniformDensity {
+    fn density(&self, _point: &Vec3) -> f64 {
+        self.density
+    }
+    fn max_density(&self) -> f64 {
+        self.density
+    }
+}
+
+impl GridDensity {
+    // `values` are in x-major order: x varies fastest, then y, then z.
+    pub fn new(minimum: Vec3, maximum: Vec3, size: [usize; 3], values: Vec<f64>) -> GridDensity {
+        let max_value = values.iter().cloned().fold(0.0, f64::max);
+        GridDensity {
+            minimum,
+            maximum,
+            size,
+            values,
+            max_value,
+        }
+    }
+
+    // A ball of turbulent density fading out towards the faces of the box.
+    pub fn cloud(minimum: Vec3, maximum: Vec3, resolution: usize) -> GridDensity {
+        let noise = Perlin::new();
+        let mut values = Vec::with_capacity(resolution * resolution * resolution);
+        for z in 0..resolution {
+            for y in 0..resolution {
+                for x in 0..resolution {
+                    let offset = Vec3::new(x as f64, y as f64, z as f64) / (resolution - 1) as f64
+                        * 2.0
+                        - Vec3::new(1.0, 1.0, 1.0);
+                    let falloff = (1.0 - offset.length()).clamp(0.0, 1.0);
+                    values.push(2.0 * falloff * (0.3 + noise.turb(&(3.0 * offset), 5)));
+                }
+            }
+        }
+        GridDensity::new(minimum, maximum, [resolution; 3], values)
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[(z * self.size[1] + y) * self.size[0] + x]
+    }
+}
+
+impl Density for GridDensity {
+    fn density(&self, point: &Vec3) -> f64 {
+        let mut cell = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let extent = self.maximum[axis] - self.minimum[axis];
+            let position = (point[axis] - self.minimum[axis]) / extent;
+            if !(0.0..=1.0).contains(&position) {
+                return 0.0;
+            }
+            let scaled = position * (self.size[axis] - 1) as f64;
+            cell[axis] = (scaled as usize).min(self.size[axis].saturating_sub(2));
+            fraction[axis] = scaled - cell[axis] as f64;
+        }
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for axis in 0..3 {
+                let upper = corner >> axis & 1 == 1 && self.size[axis] > 1;
+                index[axis] = cell[axis] + upper as usize;
+                weight *= if upper {
+                    fraction[axis]
+                } else {
+                    1.0 - fraction[axis]
+                };
+            }
+            density += weight * self.value(index[0], index[1], index[2]);
+        }
+        density
+    }
+    fn max_density(&self) -> f64 {
+        self.max_value
+    }
+}
+
+impl Density for NoiseDensity {
+    fn density(&self, point: &Vec3) -> f64 {
+        self.noise.turb(&(self.scale * *point), 7)
+    }
+    // Perlin noise stays within [-1, 1] and the octaves of the turbulence halve in
+    // weight, so their sum is below 2.
+    fn max_density(&self) -> f64 {
+        2.0
+    }
+}
+
+impl<'a> HeterogeneousMedium<'a> {
+    pub fn new(
+        boundary: Rc<dyn Hittable + 'a>,
+        density: Box<dyn Density>,
+        settings: MediumSettings,
+        phase_function: &'a dyn Material,
+    ) -> HeterogeneousMedium<'a> {
+        let is_gray = |color: Color| color.x == color.y && color.y == color.z;
+        HeterogeneousMedium {
+            boundary,
+            phase_function,
+            density,
+            settings,
+            emission: VolumeEmission {
+                color: settings.emission,
+            },
+            chromatic: !is_gray(settings.extinction) || !is_gray(settings.albedo),
+        }
+    }
+
+    fn record(
+        &self,
+        ray: &Ray,
+        t: f64,
+        material: &'a dyn Material,
+        weight: Color,
+    ) -> HitRecord<'_> {
+        HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            front_face: true,
+            u: Default::default(),
+            v: Default::default(),
+            material,
+            object_id: self as *const Self as usize,
+            weight,
+            footprint: None,
+        }
+    }
+}
+
+fn max_component(color: Color) -> f64 {
+    color.x.max(color.y).max(color.z)
+}
+
+impl<'a> Hittable for HeterogeneousMedium<'a> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        count(Counter::HeterogeneousMediumTests);
+        let mut intervals = BoundaryIntervals::new(self.boundary.as_ref(), ray, t_min, t_max);
+        let first = intervals.next()?;
+        let majorant = self.density.max_density() * max_component(self.settings.extinction);
+        if majorant <= 0.0 {
+            return None;
+        }
+        let ray_length = ray.direction.length();
+        let mut weight = Color::new(1.0, 1.0, 1.0);
+        // Free flight is memoryless, so tracking restarts at every entry.
+        for (t_entry, t_exit) in std::iter::once(first).chain(intervals) {
+            let mut t = t_entry;
+            loop {
+                t -= (1.0 - random_double()).ln() / (majorant * ray_length);
+                if t >= t_exit {
+                    break;
+                }
+                let density = self.density.density(&ray.point_at_parameter(t));
+                let extinction = density * self.settings.extinction;
+                let scattering = extinction * self.settings.albedo;
+                let absorption = extinction - scattering;
+                let null = Color::new(majorant, majorant, majorant) - extinction;
+                // Event probabilities proportional to the largest weighted coefficient of
+                // each kind; for gray media this is plain delta tracking with weights of 1.
+                let p_absorption = max_component(weight * absorption);
+                let p_scattering = max_component(weight * scattering);
+                let p_null = max_component(weight * null).max(0.0);
+                let total = p_absorption + p_scattering + p_null;
+                if total <= 0.0 {
+                    break;
+                }
+                let u = random_double() * total;
+                let (coefficient, probability) = if u < p_absorption {
+                    (absorption, p_absorption)
+                } else if u < p_absorption + p_scattering {
+                    (scattering, p_scattering)
                 } else {
-                    record1.t = fmax(record1.t, 0.0);
-                    let ray_length = ray.direction.length();
-                    let distance_inside_boundary = (record2.t - record1.t) * ray_length;
-                    let hit_distance = self.neg_inv_density * random_double().ln();
-                    if hit_distance > distance_inside_boundary {
-                        return None;
-                    } else {
-                        let t = record1.t + hit_distance / ray_length;
-                        return Some(HitRecord {
-                            t: t,
-                            p: ray.point_at_parameter(t),
-                            normal: Vec3::new(0.0, 1.0, 0.0),
-                            front_face: true,
-                            u: Default::default(),
-                            v: Default::default(),
-                            material: self.phase_function,
-                        });
-                    }
+                    (null, p_null)
+                };
+                if self.chromatic {
+                    weight *= coefficient / (majorant * probability / total);
                 }
-            } else {
-                None
+                if u < p_absorption {
+                    return Some(self.record(ray, t, &self.emission, weight));
+                } else if u < p_absorption + p_scattering {
+                    return Some(self.record(ray, t, self.phase_function, weight));
+                }
+            }
+            // The weight gathered so far is carried by the exit; the ray re-enters at
+            // the next interval from there.
+            if self.chromatic {
+                return Some(self.record(ray, t_exit, &MediumExit, weight));
             }
-        } else {
-            None
         }
+        None
     }
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
         self.boundary.bounding_box(time0, time1)
     }
 }
+
+impl Material for VolumeEmission {
+    fn scatter(
+        &self,
+        _ray_in: &Ray,
+        _record: &HitRecord,
+        _attenuation: &mut Vec3,
+        _scattered: &mut Ray,
+        _sampler: &mut dyn Sampler,
+    ) -> bool {
+        false
+    }
+    fn emitted(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        self.color
+    }
+    fn albedo(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        self.color
+    }
+}
+
+impl Material for MediumExit {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+        _sampler: &mut dyn Sampler,
+    ) -> bool {
+        *scattered = Ray::new(record.p, ray_in.direction, ray_in.time);
+        *attenuation = Color::new(1.0, 1.0, 1.0);
+        true
+    }
+    fn albedo(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::*;
+    use crate::cube::*;
+    use crate::normal::*;
+    use crate::random::*;
+    use crate::rectangle::*;
+    use crate::sphere::*;
+
+    const RAYS: usize = 20000;
+
+    fn glass() -> Dielectric {
+        Dielectric::new(RefractiveIndex::Constant(1.5), Color::default(), 0)
+    }
+
+    // Mesh primitive for the tests, intersected with the Möller-Trumbore algorithm.
+    struct Triangle<'a> {
+        vertices: [Vec3; 3],
+        material: &'a dyn Material,
+    }
+
+    impl<'a> Hittable for Triangle<'a> {
+        fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+            let [v0, v1, v2] = self.vertices;
+            let edge1 = v1 - v0;
+            let edge2 = v2 - v0;
+            let p = ray.direction.cross(&edge2);
+            let determinant = edge1.dot(&p);
+            if determinant.abs() < 1e-12 {
+                return None;
+            }
+            let to_origin = ray.origin - v0;
+            let u = to_origin.dot(&p) / determinant;
+            let q = to_origin.cross(&edge1);
+            let v = ray.direction.dot(&q) / determinant;
+            if u < 0.0 || v < 0.0 || u + v > 1.0 {
+                return None;
+            }
+            let t = edge2.dot(&q) / determinant;
+            if t <= t_min || t >= t_max {
+                return None;
+            }
+            let (front_face, normal) = make_facing_normal(ray, edge1.cross(&edge2).unit_vector());
+            Some(HitRecord {
+                t,
+                p: ray.point_at_parameter(t),
+                normal,
+                front_face,
+                material: self.material,
+                u,
+                v,
+                object_id: self as *const Self as usize,
+                weight: Color::new(1.0, 1.0, 1.0),
+                footprint: None,
+            })
+        }
+        fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+            let [v0, v1, v2] = self.vertices;
+            let delta = 0.0001;
+            Some(AABB {
+                minimum: Vec3::new(
+                    v0.x.min(v1.x).min(v2.x) - delta,
+                    v0.y.min(v1.y).min(v2.y) - delta,
+                    v0.z.min(v1.z).min(v2.z) - delta,
+                ),
+                maximum: Vec3::new(
+                    v0.x.max(v1.x).max(v2.x) + delta,
+                    v0.y.max(v1.y).max(v2.y) + delta,
+                    v0.z.max(v1.z).max(v2.z) + delta,
+                ),
+            })
+        }
+    }
+
+    // The twelve triangles of a box, with the winding of every face the same way round.
+    fn box_triangles<'a>(
+        minimum: Vec3,
+        maximum: Vec3,
+        material: &'a dyn Material,
+    ) -> Vec<Rc<dyn Hittable + 'a>> {
+        let corner = |i: usize| {
+            Vec3::new(
+                if i & 1 == 0 { minimum.x } else { maximum.x },
+                if i & 2 == 0 { minimum.y } else { maximum.y },
+                if i & 4 == 0 { minimum.z } else { maximum.z },
+            )
+        };
+        let faces = [
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+        ];
+        let mut triangles: Vec<Rc<dyn Hittable + 'a>> = Vec::new();
+        for [a, b, c, d] in faces {
+            triangles.push(Rc::new(Triangle {
+                vertices: [corner(a), corner(b), corner(c)],
+                material,
+            }));
+            triangles.push(Rc::new(Triangle {
+                vertices: [corner(a), corner(c), corner(d)],
+                material,
+            }));
+        }
+        triangles
+    }
+
+    // Two unit boxes along x with a gap of one unit between them, as a single mesh.
+    fn two_box_mesh(material: &dyn Material) -> Rc<dyn Hittable + '_> {
+        let mut triangles =
+            box_triangles(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0), material);
+        triangles.extend(box_triangles(
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(3.0, 1.0, 1.0),
+            material,
+        ));
+        Rc::new(BvhNode::new(&triangles, 0.0, 1.0))
+    }
+
+    fn intervals(boundary: &dyn Hittable, ray: &Ray) -> Vec<(f64, f64)> {
+        BoundaryIntervals::new(boundary, ray, 0.001, INFINITY).collect()
+    }
+
+    fn assert_intervals(actual: &[(f64, f64)], expected: &[(f64, f64)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a.0 - e.0).abs() < 1e-6 && (a.1 - e.1).abs() < 1e-6,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    // Fraction of rays along +x from x = -1 that pass the medium without a collision.
+    fn transmitted_fraction(medium: &dyn Hittable) -> f64 {
+        seed_random(7);
+        let ray = Ray::new(Vec3::new(-1.0, 0.3, 0.6), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let passed = (0..RAYS)
+            .filter(|_| medium.hit(&ray, 0.001, INFINITY).is_none())
+            .count();
+        passed as f64 / RAYS as f64
+    }
+
+    #[test]
+    fn sphere_is_crossed_once() {
+        let material = glass();
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 2.0, &material);
+        let ray = Ray::new(Vec3::new(-10.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let half_chord = (4.0f64 - 0.25).sqrt();
+        assert_intervals(
+            &intervals(&sphere, &ray),
+            &[(10.0 - half_chord, 10.0 + half_chord)],
+        );
+    }
+
+    #[test]
+    fn ray_starting_inside_sphere_is_inside_from_its_origin() {
+        let material = glass();
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 2.0, &material);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0), 0.0);
+        assert_intervals(&intervals(&sphere, &ray), &[(0.001, 1.0)]);
+    }
+
+    #[test]
+    fn sphere_behind_the_ray_is_missed() {
+        let material = glass();
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, -10.0), 2.0, &material);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!(intervals(&sphere, &ray).is_empty());
+    }
+
+    #[test]
+    fn cube_is_crossed_along_every_axis() {
+        let material = glass();
+        let cube = Cube::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 2.0, 3.0),
+            &material,
+        );
+        let along_x = Ray::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let along_y = Ray::new(Vec3::new(0.5, -1.0, 0.5), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let down_y = Ray::new(Vec3::new(0.5, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let along_z = Ray::new(Vec3::new(0.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert_intervals(&intervals(&cube, &along_x), &[(1.0, 2.0)]);
+        assert_intervals(&intervals(&cube, &along_y), &[(1.0, 3.0)]);
+        assert_intervals(&intervals(&cube, &down_y), &[(3.0, 5.0)]);
+        assert_intervals(&intervals(&cube, &along_z), &[(1.0, 4.0)]);
+    }
+
+    #[test]
+    fn interval_is_clipped_to_the_ray_segment() {
+        let material = glass();
+        let cube = Cube::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            &material,
+        );
+        let ray = Ray::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let clipped: Vec<_> = BoundaryIntervals::new(&cube, &ray, 0.001, 1.5).collect();
+        assert_intervals(&clipped, &[(1.0, 1.5)]);
+    }
+
+    #[test]
+    fn mesh_box_matches_cube() {
+        let material = glass();
+        let minimum = Vec3::new(0.0, 0.0, 0.0);
+        let maximum = Vec3::new(1.0, 2.0, 3.0);
+        let cube = Cube::new(minimum, maximum, &material);
+        let mesh = BvhNode::new(&box_triangles(minimum, maximum, &material), 0.0, 1.0);
+        seed_random(3);
+        for _ in 0..100 {
+            let origin = Vec3::new(0.5, 1.0, 1.5) + 10.0 * Vec3::random_in_limit(-1.0, 1.0);
+            let target = Vec3::new(
+                random_double_in_limit(0.1, 0.9),
+                random_double_in_limit(0.1, 1.9),
+                random_double_in_limit(0.1, 2.9),
+            );
+            let ray = Ray::new(origin, target - origin, 0.0);
+            assert_intervals(&intervals(&mesh, &ray), &intervals(&cube, &ray));
+        }
+    }
+
+    #[test]
+    fn non_convex_mesh_has_an_interval_per_piece() {
+        let material = glass();
+        let mesh = two_box_mesh(&material);
+        let ray = Ray::new(Vec3::new(-1.0, 0.3, 0.6), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert_intervals(&intervals(mesh.as_ref(), &ray), &[(1.0, 2.0), (3.0, 4.0)]);
+        let from_gap = Ray::new(Vec3::new(1.5, 0.3, 0.6), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert_intervals(&intervals(mesh.as_ref(), &from_gap), &[(0.5, 1.5)]);
+    }
+
+    #[test]
+    fn open_boundary_encloses_nothing() {
+        let material = glass();
+        let plane = XyRect {
+            mp: &material,
+            x0: -1.0,
+            x1: 1.0,
+            y0: -1.0,
+            y1: 1.0,
+            k: 0.0,
+        };
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!(intervals(&plane, &ray).is_empty());
+    }
+
+    #[test]
+    fn constant_medium_transmittance_follows_beer_lambert() {
+        let material = glass();
+        let cube: Rc<dyn Hittable> = Rc::new(Cube::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            &material,
+        ));
+        let medium = ConstantMedium::new(cube, 0.5, &material);
+        assert!((transmitted_fraction(&medium) - (-0.5f64).exp()).abs() < 0.015);
+    }
+
+    #[test]
+    fn constant_medium_skips_gaps_in_a_mesh() {
+        let material = glass();
+        let medium = ConstantMedium::new(two_box_mesh(&material), 0.5, &material);
+        assert!((transmitted_fraction(&medium) - (-1.0f64).exp()).abs() < 0.015);
+        seed_random(11);
+        let ray = Ray::new(Vec3::new(-1.0, 0.3, 0.6), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        for _ in 0..RAYS {
+            if let Some(record) = medium.hit(&ray, 0.001, INFINITY) {
+                assert!(record.p.x < 1.0 || record.p.x > 2.0, "{}", record.p.x);
+            }
+        }
+    }
+
+    #[test]
+    fn heterogeneous_medium_skips_gaps_in_a_mesh() {
+        let material = glass();
+        let settings = MediumSettings {
+            density: DensityKind::Uniform,
+            extinction: Color::new(0.5, 0.5, 0.5),
+            albedo: Color::new(1.0, 1.0, 1.0),
+            emission: Color::default(),
+        };
+        let medium = HeterogeneousMedium::new(
+            two_box_mesh(&material),
+            Box::new(UniformDensity { density: 1.0 }),
+            settings,
+            &material,
+        );
+        assert!((transmitted_fraction(&medium) - (-1.0f64).exp()).abs() < 0.015);
+    }
+}
@@ -9,7 +9,9 @@ use super::random::*;
 use super::random::*;
 use super::ray::*;
 use super::rectangle::*;
+use super::sparse::*;
 use super::sphere::*;
+use super::subsurface::*;
 use super::transform::*;
 use super::vec3::*;
 use super::volume::*;
@@ -19,7 +21,15 @@ pub struct World<'a> {
 }
 
 impl<'a> World<'a> {
-    pub fn new_final_scene(materials: &'a Materials) -> World<'a> {
+    // With `medium` the smoke ball is a heterogeneous medium instead of a uniform one. A
+    // `volume` takes its place, with the optical properties of `medium`. With `subsurface`
+    // the marble ball is translucent.
+    pub fn new_final_scene(
+        materials: &'a Materials,
+        medium: Option<MediumSettings>,
+        volume: Option<SparseGrid>,
+        subsurface: Option<SubsurfaceSettings>,
+    ) -> World<'a> {
         let iterations = 20;
         let mut v_hittables_1: Vec<Rc<dyn Hittable + 'a>> =
             Vec::with_capacity(iterations * iterations);
@@ -52,7 +62,55 @@ impl<'a> World<'a> {
             )));
         }
 
-        World {
+        let smoke_center = Vec3::new(360.0, 150.0, 145.0);
+        let smoke_radius = 70.0;
+        let smoke_boundary = Rc::new(Sphere::new(
+            smoke_center,
+            smoke_radius,
+            &materials.v_dielectrics[0],
+        ));
+        let smoke_phase: &dyn Material = match materials.v_anisotropics.first() {
+            Some(phase) => phase,
+            None => &materials.v_isotropics[0],
+        };
+        let smoke: Rc<dyn Hittable + 'a> = match (medium, volume) {
+            (Some(settings), Some(grid)) => Rc::new(HeterogeneousMedium::new(
+                grid.boundary(&materials.v_dielectrics[0]),
+                Box::new(grid),
+                settings,
+                smoke_phase,
+            )),
+            (Some(settings), None) => {
+                let extent = Vec3::new(smoke_radius, smoke_radius, smoke_radius);
+                Rc::new(HeterogeneousMedium::new(
+                    smoke_boundary,
+                    settings
+                        .density
+                        .make(smoke_center - extent, smoke_center + extent),
+                    settings,
+                    smoke_phase,
+                ))
+            }
+            (None, _) => Rc::new(ConstantMedium::new(smoke_boundary, 0.2, smoke_phase)),
+        };
+        let marble_center = Vec3::new(220.0, 280.0, 300.0);
+        let marble_radius = 80.0;
+        let marble: Rc<dyn Hittable + 'a> = match subsurface {
+            Some(settings) => Rc::new(Subsurface::new(
+                Rc::new(Sphere::new(
+                    marble_center,
+                    marble_radius,
+                    &materials.v_dielectrics[0],
+                )),
+                settings,
+            )),
+            None => Rc::new(Sphere::new(
+                marble_center,
+                marble_radius,
+                &materials.v_lambertians[3],
+            )),
+        };
+        let mut world = World {
             v_hittables: vec![
                 Rc::new(BvhNode::new(&v_hittables_1, 0.0, 1.0)),
                 Rc::new(Translate {
@@ -68,7 +126,10 @@ impl<'a> World<'a> {
                     z0: 147.0,
                     z1: 412.0,
                     k: 554.0,
-                    mp: &materials.v_diffuse_lights[0],
+                    mp: match materials.v_blackbody_lights.first() {
+                        Some(light) => light,
+                        None => &materials.v_diffuse_lights[0],
+                    },
                 }),
                 Rc::new(MovingSphere::new(
                     center_1,
@@ -88,15 +149,7 @@ impl<'a> World<'a> {
                     50.0,
                     &materials.v_metals[0],
                 )),
-                Rc::new(ConstantMedium::new(
-                    Rc::new(Sphere::new(
-                        Vec3::new(360.0, 150.0, 145.0),
-                        70.0,
-                        &materials.v_dielectrics[0],
-                    )),
-                    0.2,
-                    &materials.v_isotropics[0],
-                )),
+                smoke,
                 Rc::new(ConstantMedium::new(
                     Rc::new(Sphere::new(
                         Vec3::new(0.0, 0.0, 0.0),
@@ -111,18 +164,40 @@ impl<'a> World<'a> {
                     100.0,
                     &materials.v_lambertians[2],
                 )),
-                Rc::new(Sphere::new(
-                    Vec3::new(220.0, 280.0, 300.0),
-                    80.0,
-                    &materials.v_lambertians[3],
-                )),
+                marble,
             ],
+        };
+        // Nested in the glass sphere, with a higher priority so it replaces the glass.
+        if let Some(inclusion) = materials.v_dielectrics.get(1) {
+            world.v_hittables.push(Rc::new(Sphere::new(
+                Vec3::new(260.0, 150.0, 45.0),
+                25.0,
+                inclusion,
+            )));
+        }
+        // Turned to face the camera, so that the image reads left to right.
+        if let Some(cutout) = materials.v_cutouts.first() {
+            world.v_hittables.push(Rc::new(Translate {
+                offset: Vec3::new(400.0, 250.0, -200.0),
+                ptr: Rc::new(RotationY::new(
+                    Rc::new(XyRect {
+                        x0: -100.0,
+                        x1: 100.0,
+                        y0: -100.0,
+                        y1: 100.0,
+                        k: 0.0,
+                        mp: cutout,
+                    }),
+                    180.0,
+                )),
+            }));
         }
+        world
     }
 }
 
 impl<'a> Hittable for World<'a> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut closest_record = None;
         let mut closest_so_far = t_max;
         for sphere in &self.v_hittables {